@@ -0,0 +1,74 @@
+//! How fast a splash is turned into a cell with each filter and backend, with and without
+//! shrinking JPEGs while they're decoded
+//!
+//! Run with `cargo bench --bench resize`.
+
+use std::path::PathBuf;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use image::{Rgb, RgbImage};
+use rusty_splash::resize::{resize_cell, ResizeBackend, ResizeFilter, ResizeOptions};
+
+/// The size CommunityDragon's uncentered splashes come in
+const SPLASH: (u32, u32) = (1215, 717);
+
+/// Cells for a 4K monitor with a 1080p one, roughly what a tile of 40 and 12 splashes uses
+const CELLS: [(u32, u32); 2] = [(480, 283), (240, 141)];
+
+/// A splash with enough detail that the encoder and filters have work to do
+fn fixture() -> PathBuf {
+    let path = std::env::temp_dir().join("rusty-splash-bench-splash.jpg");
+    if !path.exists() {
+        RgbImage::from_fn(SPLASH.0, SPLASH.1, |x, y| {
+            Rgb([(x % 256) as u8, (y % 256) as u8, ((x * y) % 251) as u8])
+        })
+        .save(&path)
+        .unwrap();
+    }
+    path
+}
+
+fn resize(c: &mut Criterion) {
+    let path = fixture();
+    let mut group = c.benchmark_group("resize");
+    group.throughput(Throughput::Elements(1));
+    for cell in CELLS {
+        for filter in [
+            ResizeFilter::Nearest,
+            ResizeFilter::Triangle,
+            ResizeFilter::CatmullRom,
+            ResizeFilter::Gaussian,
+            ResizeFilter::Lanczos3,
+        ] {
+            for backend in [ResizeBackend::Image, ResizeBackend::Simd] {
+                for fast_decode in [false, true] {
+                    let options = ResizeOptions {
+                        filter,
+                        fast_decode: Some(fast_decode),
+                        backend,
+                    };
+                    let strategy = format!(
+                        "{filter:?}{}{}/{}x{}",
+                        if backend == ResizeBackend::Simd {
+                            "+simd"
+                        } else {
+                            ""
+                        },
+                        if fast_decode { "+dct" } else { "" },
+                        cell.0,
+                        cell.1
+                    );
+                    group.bench_with_input(
+                        BenchmarkId::from_parameter(strategy),
+                        &options,
+                        |b, options| b.iter(|| resize_cell(&path, cell, (0, 0), *options).unwrap()),
+                    );
+                }
+            }
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, resize);
+criterion_main!(benches);
@@ -1,11 +1,78 @@
-use std::{collections::HashSet, fs, path::PathBuf, u64};
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
 
 use anyhow::{anyhow, Context};
-use dirs::home_dir;
+use chrono::Utc;
+use rayon::{
+    iter::{IntoParallelIterator, ParallelIterator},
+    ThreadPoolBuilder,
+};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use uuid::Uuid;
 
-use crate::cache::Cached;
+use crate::{
+    build_cache::{BuildCache, CellCache, CELL_CACHE_LIMIT},
+    cache::Cached,
+    cdragon::{CDragon, RemoteSplash, Skin, SkinQuery, SourceKind, LATEST_PATCH},
+    config::Config,
+    palette::{arrange, Arrangement, SplashColors},
+    paths,
+    sidecar::Sidecar,
+    tiled_splash::build_tile,
+};
+
+/// How downloading a splash went
+#[derive(Debug, Clone)]
+pub struct Download {
+    pub id: u64,
+    pub path: PathBuf,
+    /// Why the download failed, `None` when it succeeded
+    pub error: Option<String>,
+}
+
+/// A wallpaper built for one monitor
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BuiltFile {
+    pub monitor: (u32, u32),
+    pub path: PathBuf,
+    /// The sidecar saying which skin sits where, see [`crate::sidecar::Sidecar`]
+    pub layout: PathBuf,
+    /// Whether nothing had changed since the last build, so the existing file was kept
+    pub reused: bool,
+}
+
+/// What [`App::tile_build`] made
+#[derive(Debug, Clone)]
+pub struct TileBuild {
+    pub tile: Uuid,
+    /// How a smart tile's query matched differently than last time
+    pub refreshed: QueryDiff,
+    pub files: Vec<BuiltFile>,
+    /// Splashes that couldn't be downloaded and were left out
+    pub failed: Vec<Download>,
+}
+
+/// What [`App::sync`] found and re-downloaded
+#[derive(Debug, Clone, Default)]
+pub struct SyncReport {
+    /// How many downloaded splashes were checked
+    pub checked: usize,
+    /// Splashes whose art changed
+    pub changed: Vec<u64>,
+    /// The re-downloads, empty for a dry run
+    pub updated: Vec<Download>,
+    /// Where the replaced splashes were moved
+    pub archived: Vec<PathBuf>,
+    /// Tiles that use re-downloaded splashes
+    pub stale_tiles: Vec<String>,
+    /// Splashes that couldn't be checked
+    pub errors: Vec<String>,
+}
 
 #[derive(Default, Debug, Serialize, Deserialize)]
 pub struct App {
@@ -15,6 +82,13 @@ pub struct App {
     pub tiles: Vec<TileInstance>,
     pub selected_tile: Uuid,
     pub monitors: Vec<(u32, u32)>,
+    /// Color analysis of downloaded splashes, keyed by the patch they're from and then skin id,
+    /// see [`App::colors`]
+    #[serde(default)]
+    pub colors: HashMap<String, HashMap<u64, SplashColors>>,
+    /// What the server said about each downloaded splash, keyed by skin id
+    #[serde(default)]
+    pub remote: HashMap<u64, RemoteSplash>,
 }
 
 impl App {
@@ -22,24 +96,20 @@ impl App {
         let mut app = App::default();
         match app.load() {
             Ok(_) => Ok(app),
-            Err(_) => {
+            Err(err) => {
+                if app.cache()?.exists() {
+                    eprintln!("starting over with a fresh app cache: {:#}", err);
+                }
                 app.monitors = monitors;
-                let mut home = home_dir().ok_or(anyhow!("couldn't get home dir"))?;
-                home.push("rusty-splash");
-                home.push("downloads");
-                if !home.exists() {
-                    fs::create_dir_all(&home)
-                        .with_context(|| anyhow!("failed to create missing downloads dir"))?;
-                }
-                app.download_path = home.clone();
-
-                home.pop();
-                home.push("tiles");
-                if !home.exists() {
-                    fs::create_dir_all(&home)
-                        .with_context(|| anyhow!("failed to create missing downloads dir"))?;
-                }
-                app.tile_path = home;
+                let paths = paths::get()?;
+                app.download_path = paths.downloads();
+                app.tile_path = paths.tiles();
+                for dir in [&app.download_path, &app.tile_path] {
+                    if !dir.exists() {
+                        fs::create_dir_all(dir)
+                            .with_context(|| format!("failed to create missing dir {:?}", dir))?;
+                    }
+                }
                 app.downloads();
                 app.save()?;
                 Ok(app)
@@ -52,8 +122,9 @@ impl App {
                 .download_path
                 .read_dir()
                 .unwrap_or_else(|err| panic!("Couldn't read downloads dir: {err}"))
-                .into_iter()
                 .filter_map(|dir| dir.ok())
+                // pinned patches are downloaded to folders of their own
+                .filter(|entry| entry.file_type().is_ok_and(|kind| kind.is_file()))
                 .map(|entry| {
                     let file_name = entry
                         .path()
@@ -73,23 +144,175 @@ impl App {
                 .collect();
         }
     }
+
+    /// Where splashes from `patch` are downloaded, pinned patches get a folder of their own so
+    /// they never replace the latest art
+    pub fn splash_dir(&self, patch: &str) -> PathBuf {
+        match patch {
+            LATEST_PATCH => self.download_path.clone(),
+            patch => self.download_path.join(patch),
+        }
+    }
+
+    pub fn splash_path(&self, id: u64, patch: &str) -> PathBuf {
+        self.splash_dir(patch).join(format!("{id}.jpg"))
+    }
+
+    /// Whether the splash for `id` from `patch` has been downloaded
+    pub fn is_downloaded(&self, id: u64, patch: &str) -> bool {
+        match patch {
+            LATEST_PATCH => self.downloaded.contains(&id),
+            patch => self.splash_path(id, patch).exists(),
+        }
+    }
+
     pub fn refresh_downloads(&mut self) {
-        self.downloads();
-        let _ = self.save();
+        let _ = self.transaction(|app| {
+            app.downloads();
+            Ok(())
+        });
+    }
+
+    /// Download `skins` with as many at once as download.concurrency allows
+    pub fn download(
+        &mut self,
+        skins: Vec<&Skin>,
+        cdragon: &CDragon,
+        config: &Config,
+    ) -> anyhow::Result<Vec<Download>> {
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(config.download.concurrency)
+            .build()?;
+        let download_path = self.splash_dir(&cdragon.patch);
+        fs::create_dir_all(&download_path)
+            .with_context(|| format!("failed to create dir path: {:?}", download_path))?;
+        let download_path = &download_path;
+        let sources = config.source.order();
+        let downloaded: Vec<(Download, Option<RemoteSplash>)> = pool.install(|| {
+            skins
+                .into_par_iter()
+                .map(|skin| {
+                    let downloaded =
+                        cdragon.download_splash(skin, download_path, config.art.kind, &sources);
+                    let result = Download {
+                        id: skin.id,
+                        path: download_path.join(format!("{}.jpg", skin.id)),
+                        error: downloaded.as_ref().err().map(|err| format!("{err:#}")),
+                    };
+                    (result, downloaded.ok())
+                })
+                .collect()
+        });
+        let mut results = Vec::new();
+        self.transaction(|app| {
+            for (result, remote) in downloaded {
+                // only the latest splashes are synced, old patches don't change
+                if let (Some(remote), LATEST_PATCH) = (remote, cdragon.patch.as_str()) {
+                    app.remote.insert(result.id, remote);
+                }
+                results.push(result);
+            }
+            app.downloads();
+            Ok(())
+        })?;
+        Ok(results)
+    }
+
+    /// Download any of `ids` that haven't been downloaded yet
+    pub fn download_missing(
+        &mut self,
+        cdragon: &CDragon,
+        ids: &[u64],
+        config: &Config,
+    ) -> anyhow::Result<Vec<Download>> {
+        let missing: Vec<&Skin> = ids
+            .iter()
+            .filter(|id| !self.is_downloaded(**id, &cdragon.patch))
+            .filter_map(|id| cdragon.skin(*id))
+            .collect();
+        match missing.is_empty() {
+            true => Ok(Vec::new()),
+            false => self.download(missing, cdragon, config),
+        }
+    }
+
+    /// Analyze the colors of any splashes in `ids` downloaded from `patch` that haven't been
+    /// analyzed yet
+    pub fn analyze_splashes(&mut self, ids: &[u64], patch: &str) -> anyhow::Result<()> {
+        let analyzed = self.colors(patch);
+        let missing: Vec<u64> = ids
+            .iter()
+            .filter(|id| self.is_downloaded(**id, patch) && !analyzed.contains_key(id))
+            .copied()
+            .collect();
+        if missing.is_empty() {
+            return Ok(());
+        }
+        let analyzed: anyhow::Result<Vec<(u64, SplashColors)>> = missing
+            .into_par_iter()
+            .map(|id| {
+                let path = self.splash_path(id, patch);
+                Ok((id, SplashColors::analyze(&path)?))
+            })
+            .collect();
+        let analyzed = analyzed?;
+        self.transaction(|app| {
+            app.colors
+                .entry(patch.to_string())
+                .or_default()
+                .extend(analyzed);
+            Ok(())
+        })
+    }
+
+    /// The colors analyzed from the splashes of `patch`, keyed by skin id
+    ///
+    /// Pinned patches can have different art for a skin, so each patch's splashes are analyzed
+    /// on their own.
+    pub fn colors(&self, patch: &str) -> Cow<'_, HashMap<u64, SplashColors>> {
+        self.colors
+            .get(patch)
+            .map(Cow::Borrowed)
+            .unwrap_or_default()
+    }
+
+    /// Find a tile by its name or id, or the selected tile when `name_or_id` is `None`
+    pub fn tile_find(&self, name_or_id: Option<&str>) -> anyhow::Result<&TileInstance> {
+        let Some(name_or_id) = name_or_id else {
+            return self
+                .tiles
+                .iter()
+                .find(|tile| tile.id == self.selected_tile)
+                .ok_or(anyhow!("no tile is selected"));
+        };
+        if let Ok(id) = Uuid::parse_str(name_or_id) {
+            if let Some(tile) = self.tiles.iter().find(|tile| tile.id == id) {
+                return Ok(tile);
+            }
+        }
+        let mut matches = self.tiles.iter().filter(|tile| tile.name == name_or_id);
+        match (matches.next(), matches.next()) {
+            (Some(tile), None) => Ok(tile),
+            (Some(_), Some(_)) => Err(anyhow!(
+                "more than one tile is named {name_or_id}, use its id instead"
+            )),
+            (None, _) => Err(anyhow!("tile {name_or_id} not found")),
+        }
     }
 
     pub fn tile_select(&mut self, tile_id: Uuid) -> anyhow::Result<()> {
-        match self.selected_tile == tile_id {
-            true => Ok(()),
-            false => match self.tiles.iter().any(|tile| tile.id == tile_id) {
+        if self.selected_tile == tile_id {
+            return Ok(());
+        }
+        self.transaction(
+            |app| match app.tiles.iter().any(|tile| tile.id == tile_id) {
                 true => {
-                    self.selected_tile = tile_id;
-                    self.save()?;
+                    app.selected_tile = tile_id;
                     Ok(())
                 }
                 false => Err(anyhow!("tile {} not found", tile_id)),
             },
-        }
+        )
     }
 
     pub fn tile_new<S>(&mut self, name: S) -> anyhow::Result<Uuid>
@@ -97,56 +320,403 @@ impl App {
         S: Into<String>,
     {
         let new_tile = TileInstance::new(name);
-        let id = new_tile.id;
-        self.tiles.push(new_tile);
-        self.save()?;
-        Ok(id)
+        self.tile_insert(new_tile)
     }
 
     pub fn tile_update_name<S>(&mut self, id: Uuid, name: S) -> anyhow::Result<()>
     where
         S: Into<String>,
     {
-        let tile = self.tiles.iter_mut().find(|tile| tile.id == id);
-        match tile {
-            Some(tile) => {
-                tile.name = name.into();
-                self.save()?;
+        self.transaction(
+            |app| match app.tiles.iter_mut().find(|tile| tile.id == id) {
+                Some(tile) => {
+                    tile.name = name.into();
+                    Ok(())
+                }
+                None => Err(anyhow!(
+                    "couldn't update the name of tile {:?}, because it doesn't exist",
+                    id
+                )),
+            },
+        )
+    }
+
+    /// Copy a tile's splashes and settings into a new tile called `name`
+    pub fn tile_duplicate<S>(&mut self, id: Uuid, name: S) -> anyhow::Result<Uuid>
+    where
+        S: Into<String>,
+    {
+        self.transaction(|app| {
+            let mut duplicate = app
+                .tiles
+                .iter()
+                .find(|tile| tile.id == id)
+                .ok_or(anyhow!(
+                    "couldn't duplicate tile {:?}, because it doesn't exist",
+                    id
+                ))?
+                .clone();
+            duplicate.id = Uuid::new_v4();
+            duplicate.set_name(name);
+            let id = duplicate.id;
+            app.tiles.push(duplicate);
+            Ok(id)
+        })
+    }
+
+    pub fn tile_clear(&mut self, id: Uuid) -> anyhow::Result<()> {
+        self.transaction(
+            |app| match app.tiles.iter_mut().find(|tile| tile.id == id) {
+                Some(tile) => {
+                    tile.ensure_static()?;
+                    tile.splash_ids.clear();
+                    Ok(())
+                }
+                None => Err(anyhow!("no tile {:?} found", id)),
+            },
+        )
+    }
+
+    pub fn tile_delete(&mut self, id: Uuid) -> anyhow::Result<()> {
+        self.transaction(|app| {
+            let index = app.tiles.iter().position(|tile| tile.id == id);
+            if let Some(index) = index {
+                app.tiles.remove(index);
+                if app.selected_tile == id {
+                    app.selected_tile = Uuid::default();
+                }
                 Ok(())
+            } else {
+                Err(anyhow!("tile {} not found!", id))
             }
-            None => Err(anyhow!(
-                "couldn't update the name of tile {:?}, because it doesn't exist",
-                id
-            )),
+        })
+    }
+
+    pub fn tile_add_splash(&mut self, id: &Uuid, splash_id: &u64) -> anyhow::Result<()> {
+        self.transaction(
+            |app| match app.tiles.iter_mut().find(|tile| tile.id == *id) {
+                Some(tile) => {
+                    tile.ensure_static()?;
+                    tile.add_splashes(&[*splash_id]);
+                    Ok(())
+                }
+                None => Err(anyhow!(
+                    "couldn't add splashes to tile {:?}, because it doesn't exst",
+                    id
+                )),
+            },
+        )
+    }
+
+    pub fn tile_set_arrangement(
+        &mut self,
+        id: Uuid,
+        arrangement: Arrangement,
+    ) -> anyhow::Result<()> {
+        self.transaction(
+            |app| match app.tiles.iter_mut().find(|tile| tile.id == id) {
+                Some(tile) => {
+                    tile.arrangement = arrangement;
+                    Ok(())
+                }
+                None => Err(anyhow!("no tile {:?} found", id)),
+            },
+        )
+    }
+
+    /// Keep `monitors` as the ones to build for, unless the app already knows its monitors
+    pub fn remember_monitors(&mut self, monitors: Vec<(u32, u32)>) -> anyhow::Result<()> {
+        if !self.monitors.is_empty() {
+            return Ok(());
         }
+        self.transaction(|app| {
+            if app.monitors.is_empty() {
+                app.monitors = monitors;
+            }
+            Ok(())
+        })
     }
 
-    pub fn tile_delete(&mut self, id: Uuid) -> anyhow::Result<()> {
-        let index = self.tiles.iter().position(|tile| tile.id == id);
-        if let Some(index) = index {
-            self.tiles.remove(index);
-            if self.selected_tile == id {
-                self.selected_tile = Uuid::default();
+    pub fn tile_set_layout(&mut self, id: Uuid, layout: TileLayout) -> anyhow::Result<()> {
+        self.transaction(
+            |app| match app.tiles.iter_mut().find(|tile| tile.id == id) {
+                Some(tile) => {
+                    tile.layout = layout;
+                    Ok(())
+                }
+                None => Err(anyhow!("no tile {:?} found", id)),
+            },
+        )
+    }
+
+    /// Add an already built tile, like one loaded from a preset
+    pub fn tile_insert(&mut self, tile: TileInstance) -> anyhow::Result<Uuid> {
+        let id = tile.id;
+        self.transaction(|app| {
+            app.tiles.push(tile);
+            Ok(id)
+        })
+    }
+
+    /// Re-run a smart tile's query, keeping the order of skins that still match and appending
+    /// new ones
+    ///
+    /// Static tiles are left alone and return an empty diff.
+    pub fn tile_refresh(&mut self, id: Uuid, cdragon: &CDragon) -> anyhow::Result<QueryDiff> {
+        self.transaction(|app| {
+            app.tiles
+                .iter_mut()
+                .find(|tile| tile.id == id)
+                .ok_or(anyhow!("no tile {:?} found", id))?
+                .refresh(cdragon)
+        })
+    }
+
+    /// Refresh a tile, download its missing splashes and build it for every monitor
+    ///
+    /// Splashes that fail to download are left out and reported in [`TileBuild::failed`].
+    pub fn tile_build(
+        &mut self,
+        id: Uuid,
+        cdragon: &CDragon,
+        config: &Config,
+    ) -> anyhow::Result<TileBuild> {
+        let refreshed = self.tile_refresh(id, cdragon)?;
+        let tile = self.tile_find(Some(&id.to_string()))?.clone();
+        let failed: Vec<Download> = self
+            .download_missing(cdragon, &tile.splash_ids, config)?
+            .into_iter()
+            .filter(|download| download.error.is_some())
+            .collect();
+        if tile.arrangement != Arrangement::None {
+            self.analyze_splashes(&tile.splash_ids, &cdragon.patch)?;
+        }
+        let splash_ids: Vec<u64> = arrange(
+            &tile.splash_ids,
+            &self.colors(&cdragon.patch),
+            tile.arrangement,
+        )
+        .into_iter()
+        .filter(|id| self.is_downloaded(*id, &cdragon.patch))
+        .collect();
+        let splash_paths: Vec<PathBuf> = splash_ids
+            .iter()
+            .map(|id| self.splash_path(*id, &cdragon.patch))
+            .collect();
+        if splash_paths.is_empty() {
+            return Err(anyhow!("{} doesn't have any splashes yet!", tile.name));
+        }
+        let cells = CellCache::open()?;
+        let builds = BuildCache::read()?;
+        let mut files = Vec::new();
+        for (index, monitor) in self.monitors.iter().enumerate() {
+            let name = format!("{}-{index}", paths::file_name(&tile.name));
+            let options = config.tile.build_options();
+            let fingerprint = cells.fingerprint(
+                &cdragon.patch,
+                &splash_paths,
+                *monitor,
+                tile.layout.min_cell,
+                options,
+            )?;
+            // wallpapers built before sidecars were written get one on their next build
+            let current = tile_files(&self.tile_path, &name)
+                .into_iter()
+                .find(|built| {
+                    builds.is_current(built, &fingerprint) && Sidecar::path(built).exists()
+                });
+            if let Some(built) = current {
+                files.push(BuiltFile {
+                    monitor: *monitor,
+                    layout: Sidecar::path(&built),
+                    path: built,
+                    reused: true,
+                });
+                continue;
+            }
+            let built = build_tile(
+                &splash_paths,
+                *monitor,
+                tile.layout.min_cell,
+                name.clone(),
+                &self.tile_path,
+                options,
+                &cells,
+            )?;
+            let Some(built) = built else {
+                files.push(BuiltFile {
+                    monitor: *monitor,
+                    path: PathBuf::default(),
+                    layout: PathBuf::default(),
+                    reused: false,
+                });
+                continue;
+            };
+            // a build in the other format is stale now
+            for stale in tile_files(&self.tile_path, &name) {
+                if stale != built.path && stale.exists() {
+                    fs::remove_file(&stale)
+                        .with_context(|| format!("failed to remove {:?}", stale))?;
+                }
+            }
+            let layout = Sidecar::new(
+                &built.path,
+                *monitor,
+                &built.geometry,
+                &splash_ids,
+                &splash_paths,
+                cdragon,
+            )?
+            .save(&built.path)?;
+            BuildCache::record(&built.path, fingerprint)?;
+            files.push(BuiltFile {
+                monitor: *monitor,
+                path: built.path,
+                layout,
+                reused: false,
+            });
+        }
+        cells.prune(CELL_CACHE_LIMIT)?;
+        self.transaction(|app| {
+            if let Some(tile) = app.tiles.iter_mut().find(|tile| tile.id == id) {
+                tile.needs_rebuild = false;
             }
-            self.save()?;
             Ok(())
-        } else {
-            Err(anyhow!("tile {} not found!", id))
+        })?;
+        Ok(TileBuild {
+            tile: id,
+            refreshed,
+            files,
+            failed,
+        })
+    }
+
+    /// Check every downloaded splash for new art, re-downloading the ones that changed
+    ///
+    /// Replaced splashes are moved to the archive and tiles using them are marked as needing a
+    /// rebuild. Splashes downloaded before their metadata was kept are compared by size, then
+    /// remembered for next time. Only the latest splashes are checked, pinned patches don't change.
+    pub fn sync(
+        &mut self,
+        cdragon: &CDragon,
+        config: &Config,
+        dry_run: bool,
+    ) -> anyhow::Result<SyncReport> {
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(config.download.concurrency)
+            .build()?;
+        let ids: Vec<u64> = self.downloaded.iter().copied().collect();
+        let checks: Vec<(u64, anyhow::Result<(bool, RemoteSplash)>)> = pool.install(|| {
+            ids.into_par_iter()
+                .map(|id| (id, self.check_splash(id, cdragon, config)))
+                .collect()
+        });
+        let mut result = SyncReport {
+            checked: checks.len(),
+            ..SyncReport::default()
+        };
+        let mut remotes = HashMap::new();
+        let mut skins: Vec<&Skin> = Vec::new();
+        for (id, check) in checks {
+            match check {
+                Ok((true, _)) => match cdragon.skin(id) {
+                    Some(skin) => skins.push(skin),
+                    None => result.errors.push(format!(
+                        "{id}: changed, but it's not in the skin data anymore"
+                    )),
+                },
+                Ok((false, latest)) => {
+                    remotes.insert(id, latest);
+                }
+                Err(err) => result.errors.push(format!("{id}: {err:#}")),
+            }
+        }
+        skins.sort_unstable_by_key(|skin| skin.id);
+        result.changed = skins.iter().map(|skin| skin.id).collect();
+        if dry_run {
+            return Ok(result);
+        }
+        let archive = paths::get()?.archive();
+        fs::create_dir_all(&archive)
+            .with_context(|| format!("failed to create dir path: {:?}", archive))?;
+        let stamp = Utc::now().format("%Y%m%d%H%M%S");
+        let sources = config.source.order();
+        let mut replaced = HashSet::new();
+        for skin in skins {
+            let path = self.download_path.join(format!("{}.jpg", skin.id));
+            let archived = archive.join(format!("{}-{stamp}.jpg", skin.id));
+            let downloaded = replace_splash(&path, &archived, || {
+                cdragon.download_splash(skin, &self.download_path, config.art.kind, &sources)
+            })?;
+            let error = match downloaded {
+                Ok(remote) => {
+                    remotes.insert(skin.id, remote);
+                    replaced.insert(skin.id);
+                    result.archived.push(archived);
+                    None
+                }
+                Err(err) => Some(format!("{err:#}")),
+            };
+            result.updated.push(Download {
+                id: skin.id,
+                path,
+                error,
+            });
         }
+        self.transaction(|app| {
+            app.remote.extend(remotes);
+            result.stale_tiles = app.forget_replaced(&replaced);
+            Ok(())
+        })?;
+        Ok(result)
     }
 
-    pub fn tile_add_splash(&mut self, id: &Uuid, splash_id: &u64) -> anyhow::Result<()> {
-        match self.tiles.iter_mut().find(|tile| tile.id == *id) {
-            Some(tile) => {
-                tile.splash_ids.insert(*splash_id);
-                self.save()?;
-                Ok(())
+    /// Drop what's known about the art of `replaced` splashes and mark the tiles using them as
+    /// needing a rebuild, returning their names
+    fn forget_replaced(&mut self, replaced: &HashSet<u64>) -> Vec<String> {
+        // sync only replaces splashes of the latest patch
+        if let Some(colors) = self.colors.get_mut(LATEST_PATCH) {
+            colors.retain(|id, _| !replaced.contains(id));
+        }
+        let mut stale = Vec::new();
+        for tile in self.tiles.iter_mut() {
+            if tile.splash_ids.iter().any(|id| replaced.contains(id)) {
+                tile.needs_rebuild = true;
+                stale.push(tile.name.clone());
             }
-            None => Err(anyhow!(
-                "couldn't add splashes to tile {:?}, because it doesn't exst",
-                id
-            )),
         }
+        stale
+    }
+
+    /// Whether the splash for `id` has changed, and what the server says about it now
+    fn check_splash(
+        &self,
+        id: u64,
+        cdragon: &CDragon,
+        config: &Config,
+    ) -> anyhow::Result<(bool, RemoteSplash)> {
+        let stored = self.remote.get(&id);
+        let url = match stored {
+            Some(stored) => stored.url.clone(),
+            None => {
+                let skin = cdragon
+                    .skin(id)
+                    .ok_or(anyhow!("not in the skin data anymore"))?;
+                cdragon.art_url(skin, config.art.kind, config.source.primary)?
+            }
+        };
+        let latest = RemoteSplash::head(&url)?;
+        let changed = match stored {
+            Some(stored) => stored.changed(&latest),
+            None => {
+                let path = self.download_path.join(format!("{id}.jpg"));
+                let size = fs::metadata(&path)
+                    .with_context(|| format!("failed to read {:?}", path))?
+                    .len();
+                latest.size.is_some_and(|latest| latest != size)
+            }
+        };
+        Ok((changed, latest))
     }
 
     pub fn tile_remove_splashes(
@@ -154,31 +724,134 @@ impl App {
         id: Uuid,
         splash_ids: &HashSet<u64>,
     ) -> anyhow::Result<()> {
-        match self.tiles.iter_mut().find(|tile| tile.id == id) {
-            Some(tile) => {
-                tile.remove_splashes(splash_ids);
-                self.save()?;
-                return Ok(());
-            }
-            None => Err(anyhow!("no tile {:?} found", id)),
-        }
+        self.transaction(
+            |app| match app.tiles.iter_mut().find(|tile| tile.id == id) {
+                Some(tile) => {
+                    tile.ensure_static()?;
+                    tile.remove_splashes(splash_ids);
+                    Ok(())
+                }
+                None => Err(anyhow!("no tile {:?} found", id)),
+            },
+        )
+    }
+
+    /// The built wallpaper for each monitor, whichever format it was saved in
+    pub fn built_files(&self, tile: &TileInstance) -> Vec<PathBuf> {
+        (0..self.monitors.len())
+            .map(|index| {
+                let name = format!("{}-{index}", paths::file_name(&tile.name));
+                let [jpg, png] = tile_files(&self.tile_path, &name);
+                match !jpg.exists() && png.exists() {
+                    true => png,
+                    false => jpg,
+                }
+            })
+            .collect()
     }
 }
 
+/// Move the splash at `path` to `archived` and `download` it again, moving it back if the
+/// download fails
+///
+/// Only failing to move the files is an error, the download's result is returned as is.
+fn replace_splash<T>(
+    path: &Path,
+    archived: &Path,
+    download: impl FnOnce() -> anyhow::Result<T>,
+) -> anyhow::Result<anyhow::Result<T>> {
+    fs::rename(path, archived).with_context(|| format!("failed to archive {:?}", path))?;
+    let downloaded = download();
+    if downloaded.is_err() {
+        fs::rename(archived, path).with_context(|| format!("failed to restore {:?}", path))?;
+    }
+    Ok(downloaded)
+}
+
+/// Where a wallpaper called `name` is saved, tiles too big to build in memory are PNGs
+fn tile_files(dir: &Path, name: &str) -> [PathBuf; 2] {
+    [
+        dir.join(format!("{name}.jpg")),
+        dir.join(format!("{name}.png")),
+    ]
+}
+
 impl Cached for App {
     fn cache_name() -> String {
         String::from("app")
     }
+
+    fn migrate(mut value: Value, from: u32) -> anyhow::Result<Value> {
+        if from == 0 {
+            // tiles used to be selected by the caller rather than remembered
+            if let Some(app) = value.as_object_mut() {
+                app.entry("selected_tile")
+                    .or_insert_with(|| Value::String(Uuid::nil().to_string()));
+            }
+            // monitors used to be saved as {"width": 3840, "height": 1600} objects
+            if let Some(monitors) = value.get_mut("monitors").and_then(Value::as_array_mut) {
+                for monitor in monitors {
+                    if let (Some(width), Some(height)) =
+                        (monitor.get("width"), monitor.get("height"))
+                    {
+                        *monitor = Value::Array(vec![width.clone(), height.clone()]);
+                    }
+                }
+            }
+        }
+        Ok(value)
+    }
 }
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct TileInstance {
     pub id: Uuid,
     pub name: String,
-    pub splash_ids: HashSet<u64>,
+    /// The splashes in the order they were added
+    pub splash_ids: Vec<u64>,
+    #[serde(default)]
+    pub arrangement: Arrangement,
+    #[serde(default)]
+    pub layout: TileLayout,
+    #[serde(default)]
+    pub source: TileSource,
+    /// Set when splashes in the tile were re-downloaded since it was last built
+    #[serde(default)]
+    pub needs_rebuild: bool,
     path: PathBuf,
 }
 
+/// Where a tile's splashes come from
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TileSource {
+    /// Splashes are added and removed by hand
+    #[default]
+    Static,
+    /// Splashes are whatever matches the query when the tile is built
+    Query(SkinQuery),
+}
+
+/// The change in a smart tile's splashes after its query is re-run
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QueryDiff {
+    pub added: Vec<u64>,
+    pub removed: Vec<u64>,
+}
+
+impl QueryDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// Constraints on how a tile's cells are sized
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TileLayout {
+    /// Cells are never shrunk below this size, `(0, 0)` for no limit
+    pub min_cell: (u32, u32),
+}
+
 impl TileInstance {
     pub fn new<S>(name: S) -> Self
     where
@@ -190,15 +863,65 @@ impl TileInstance {
             ..Self::default()
         }
     }
-    pub fn add_splashes(&mut self, ids: &HashSet<u64>) {
-        ids.iter().for_each(|id| {
-            self.splash_ids.insert(*id);
-        });
+    /// Append splashes that aren't already in the tile
+    pub fn add_splashes(&mut self, ids: &[u64]) {
+        for id in ids {
+            if !self.splash_ids.contains(id) {
+                self.splash_ids.push(*id);
+            }
+        }
     }
 
     pub fn remove_splashes(&mut self, ids: &HashSet<u64>) {
-        for id in ids {
-            let _ = self.splash_ids.remove(id);
+        self.splash_ids.retain(|id| !ids.contains(id));
+    }
+
+    /// A tile whose splashes are whatever matches `query`
+    pub fn with_query<S>(name: S, query: SkinQuery) -> Self
+    where
+        S: Into<String>,
+    {
+        Self {
+            source: TileSource::Query(query),
+            ..Self::new(name)
+        }
+    }
+
+    /// Re-run a smart tile's query, leaving it alone when the skins came from a source without
+    /// skin lines or rarities
+    pub fn refresh(&mut self, cdragon: &CDragon) -> anyhow::Result<QueryDiff> {
+        let TileSource::Query(query) = &self.source else {
+            return Ok(QueryDiff::default());
+        };
+        if cdragon.source != SourceKind::CommunityDragon {
+            return Ok(QueryDiff::default());
+        }
+        let matched: Vec<u64> = cdragon.search(query)?.iter().map(|skin| skin.id).collect();
+        let diff = QueryDiff {
+            added: matched
+                .iter()
+                .filter(|id| !self.splash_ids.contains(id))
+                .copied()
+                .collect(),
+            removed: self
+                .splash_ids
+                .iter()
+                .filter(|id| !matched.contains(id))
+                .copied()
+                .collect(),
+        };
+        self.splash_ids.retain(|id| matched.contains(id));
+        self.add_splashes(&diff.added);
+        Ok(diff)
+    }
+
+    fn ensure_static(&self) -> anyhow::Result<()> {
+        match &self.source {
+            TileSource::Static => Ok(()),
+            TileSource::Query(query) => Err(anyhow!(
+                "{} is defined by a query ({query}), its splashes can't be changed by hand",
+                self.name
+            )),
         }
     }
 
@@ -211,10 +934,23 @@ impl TileInstance {
 }
 
 #[cfg(test)]
+#[allow(clippy::cmp_owned, clippy::search_is_some)]
 mod test {
+    use std::{
+        collections::{HashMap, HashSet},
+        fs,
+    };
+
+    use anyhow::anyhow;
     use display_info::DisplayInfo;
 
-    use crate::app::App;
+    use crate::{
+        app::{replace_splash, App, TileInstance},
+        cache::Cached,
+        cdragon::{fixtures, CDragon, SkinQuery, SourceKind, LATEST_PATCH},
+        palette::SplashColors,
+        paths,
+    };
 
     #[test]
     fn load_app() {
@@ -248,4 +984,185 @@ mod test {
             .find(|tile| tile.name == String::from("testy"))
             .is_some());
     }
+
+    #[test]
+    fn duplicate_and_clear_tile() {
+        let mut app = App::default();
+        let original = app.tile_new("original").unwrap();
+        app.tile_add_splash(&original, &1000).unwrap();
+        app.tile_add_splash(&original, &1001).unwrap();
+        app.tile_add_splash(&original, &1000).unwrap();
+        let copy = app.tile_duplicate(original, "copy").unwrap();
+        assert_ne!(original, copy);
+        app.tile_clear(original).unwrap();
+        assert!(app
+            .tile_find(Some("original"))
+            .unwrap()
+            .splash_ids
+            .is_empty());
+        assert_eq!(
+            app.tile_find(Some("copy")).unwrap().splash_ids,
+            vec![1000, 1001]
+        );
+    }
+
+    #[test]
+    fn refresh_smart_tile() {
+        let cdragon = fixtures::cdragon();
+        let query = SkinQuery {
+            skin_line: Some(String::from("Star Guardian")),
+            ..SkinQuery::default()
+        };
+        let mut tile = TileInstance::with_query("stars", query);
+        let diff = tile.refresh(&cdragon).unwrap();
+        assert_eq!(diff.added, vec![103015, 222020]);
+        assert!(diff.removed.is_empty());
+        assert!(tile.refresh(&cdragon).unwrap().is_empty());
+
+        tile.splash_ids = vec![222020, 1000];
+        let diff = tile.refresh(&cdragon).unwrap();
+        assert_eq!(diff.added, vec![103015]);
+        assert_eq!(diff.removed, vec![1000]);
+        assert_eq!(tile.splash_ids, vec![222020, 103015]);
+
+        let mut app = App::default();
+        let id = app.tile_insert(tile).unwrap();
+        assert!(app.tile_add_splash(&id, &1001).is_err());
+    }
+
+    #[test]
+    fn replaced_splashes_are_archived() {
+        let dir = paths::get().unwrap().downloads();
+        fs::create_dir_all(&dir).unwrap();
+        let (path, archived) = (dir.join("1000.jpg"), dir.join("1000-old.jpg"));
+        fs::write(&path, b"old").unwrap();
+
+        let failed = replace_splash(&path, &archived, || -> anyhow::Result<()> {
+            Err(anyhow!("offline"))
+        })
+        .unwrap();
+        assert!(failed.is_err());
+        assert_eq!(fs::read(&path).unwrap(), b"old");
+        assert!(!archived.exists());
+
+        replace_splash(&path, &archived, || {
+            fs::write(&path, b"new").map_err(Into::into)
+        })
+        .unwrap()
+        .unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"new");
+        assert_eq!(fs::read(&archived).unwrap(), b"old");
+    }
+
+    #[test]
+    fn replaced_splashes_need_a_rebuild() {
+        let mut app = App::default();
+        app.tile_new("annie").unwrap();
+        app.tile_new("ahri").unwrap();
+        app.tiles[0].add_splashes(&[1000, 1001]);
+        app.tiles[1].add_splashes(&[103015]);
+        for patch in [LATEST_PATCH, "14.8"] {
+            app.colors.insert(
+                patch.to_string(),
+                HashMap::from([(1001, SplashColors::default())]),
+            );
+        }
+        let stale = app.forget_replaced(&HashSet::from([1001]));
+        assert_eq!(stale, vec![String::from("annie")]);
+        assert!(app.tiles[0].needs_rebuild);
+        assert!(!app.tiles[1].needs_rebuild);
+        assert!(app.colors(LATEST_PATCH).is_empty());
+        assert_eq!(app.colors("14.8").len(), 1);
+    }
+
+    #[test]
+    fn splashes_are_kept_per_patch() {
+        let mut app = App {
+            download_path: paths::get().unwrap().downloads(),
+            ..App::default()
+        };
+        let pinned = app.splash_path(1000, "14.8");
+        assert_eq!(pinned, app.download_path.join("14.8").join("1000.jpg"));
+        assert_eq!(
+            app.splash_path(1000, LATEST_PATCH),
+            app.download_path.join("1000.jpg")
+        );
+        fs::create_dir_all(pinned.parent().unwrap()).unwrap();
+        fs::write(&pinned, b"").unwrap();
+        app.downloads();
+        assert!(app.is_downloaded(1000, "14.8"));
+        assert!(!app.is_downloaded(1000, LATEST_PATCH));
+    }
+
+    #[test]
+    fn smart_tiles_keep_their_skins_on_a_fallback() {
+        let cdragon = CDragon {
+            source: SourceKind::DataDragon,
+            ..fixtures::cdragon()
+        };
+        let query = SkinQuery {
+            skin_line: Some(String::from("Star Guardian")),
+            ..SkinQuery::default()
+        };
+        let mut tile = TileInstance::with_query("stars", query);
+        tile.splash_ids = vec![222020, 103015];
+        assert!(tile.refresh(&cdragon).unwrap().is_empty());
+        assert_eq!(tile.splash_ids, vec![222020, 103015]);
+    }
+
+    #[test]
+    fn migrate_unversioned_app() {
+        let app = App::from_cached_str(include_str!("../sample_responses/app.json")).unwrap();
+        assert_eq!(app.monitors, vec![(3840, 1600), (1440, 2560)]);
+        assert_eq!(app.downloaded.len(), 3);
+
+        let resaved: serde_json::Value =
+            serde_json::from_str(&app.to_cached_string().unwrap()).unwrap();
+        assert_eq!(resaved["version"], App::VERSION);
+        assert_eq!(
+            App::from_cached_str(&resaved.to_string()).unwrap().monitors,
+            app.monitors
+        );
+    }
+
+    #[test]
+    fn newer_cache_version() {
+        assert!(App::from_cached_str(r#"{"version": 999}"#).is_err());
+    }
+
+    #[test]
+    fn stale_apps_dont_clobber() {
+        let mut first = App::default();
+        let mut second = App::default();
+        first.tile_new("first").unwrap();
+        second.tile_new("second").unwrap();
+        assert_eq!(second.tiles.len(), 2);
+
+        let batched = first
+            .transaction(|app| {
+                let id = app.tile_new("batched")?;
+                app.tile_add_splash(&id, &1000)?;
+                Ok(id)
+            })
+            .unwrap();
+        let mut reloaded = App::default();
+        reloaded.load().unwrap();
+        assert_eq!(reloaded.tiles.len(), 3);
+        assert!(reloaded.tile_find(Some(&batched.to_string())).is_ok());
+    }
+
+    #[test]
+    fn find_tile() {
+        let mut app = App::default();
+        let first = app.tile_new("first").unwrap();
+        let second = app.tile_new("second").unwrap();
+        app.tile_new("second").unwrap();
+        assert!(app.tile_find(None).is_err());
+        app.tile_select(first).unwrap();
+        assert_eq!(app.tile_find(None).unwrap().id, first);
+        assert_eq!(app.tile_find(Some("first")).unwrap().id, first);
+        assert_eq!(app.tile_find(Some(&second.to_string())).unwrap().id, second);
+        assert!(app.tile_find(Some("second")).is_err());
+        assert!(app.tile_find(Some("third")).is_err());
+    }
 }
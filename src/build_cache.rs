@@ -0,0 +1,340 @@
+use std::{
+    cmp::Reverse,
+    collections::HashMap,
+    fs::{self, File},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::SystemTime,
+};
+
+use anyhow::Context;
+use image::{DynamicImage, ImageFormat};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cache::Cached,
+    paths::{self, Location},
+    resize::ResizeOptions,
+    tiled_splash::{BuildOptions, MB},
+};
+
+/// Bumped whenever the way tiles are drawn changes, so nothing built before is reused
+const BUILD_FORMAT: u32 = 3;
+
+/// How big the cell cache grows before the least recently used cells are removed
+pub const CELL_CACHE_LIMIT: u64 = 2048 * MB;
+
+/// Resized splashes kept between builds, so cells whose splash and size haven't changed aren't
+/// decoded and resized again
+#[derive(Debug)]
+pub struct CellCache {
+    dir: PathBuf,
+    /// Hashes of the splashes seen so far, so each file is only read once
+    hashes: Mutex<HashMap<PathBuf, String>>,
+}
+
+impl CellCache {
+    /// The cell cache in the cache directory
+    pub fn open() -> anyhow::Result<Self> {
+        Self::new(paths::get()?.cells())
+    }
+
+    pub fn new(dir: PathBuf) -> anyhow::Result<Self> {
+        fs::create_dir_all(&dir)
+            .with_context(|| format!("failed to create dir path: {:?}", dir))?;
+        Ok(Self {
+            dir,
+            hashes: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// The hash of the contents of the file at `path`
+    pub fn hash(&self, path: &Path) -> anyhow::Result<String> {
+        if let Some(hash) = self.hashes.lock().unwrap().get(path) {
+            return Ok(hash.clone());
+        }
+        let contents = fs::read(path).with_context(|| format!("failed to read {:?}", path))?;
+        let hash = blake3::hash(&contents).to_hex().to_string();
+        self.hashes
+            .lock()
+            .unwrap()
+            .insert(path.to_path_buf(), hash.clone());
+        Ok(hash)
+    }
+
+    /// The splash at `path` resized to `size` with `resize` and cropped by `crop`, made with
+    /// `make` and kept for next time when it isn't cached yet
+    pub fn cell(
+        &self,
+        path: &Path,
+        size: (u32, u32),
+        crop: (u32, u32),
+        resize: ResizeOptions,
+        make: impl FnOnce() -> anyhow::Result<DynamicImage>,
+    ) -> anyhow::Result<DynamicImage> {
+        let key = blake3::hash(
+            format!(
+                "{BUILD_FORMAT}:{}:{}x{}:{}x{}:{:?}",
+                self.hash(path)?,
+                size.0,
+                size.1,
+                crop.0,
+                crop.1,
+                resize
+            )
+            .as_bytes(),
+        )
+        .to_hex();
+        let cell_path = self.dir.join(format!("{key}.png"));
+        if let Ok(cell) = image::open(&cell_path) {
+            // pruning goes by modification time, so a cell that's used is kept longer
+            let _ = File::options()
+                .write(true)
+                .open(&cell_path)
+                .and_then(|file| file.set_modified(SystemTime::now()));
+            return Ok(cell);
+        }
+        let cell = make()?;
+        // written next to the cell and renamed, so a build running at the same time never
+        // reads half a file
+        let partial = self.dir.join(format!(".{key}.{}.tmp", std::process::id()));
+        cell.save_with_format(&partial, ImageFormat::Png)
+            .with_context(|| format!("failed to cache a cell for {:?}", path))?;
+        fs::rename(&partial, &cell_path)
+            .with_context(|| format!("failed to cache a cell for {:?}", path))?;
+        Ok(cell)
+    }
+
+    /// Remove the least recently used cells until the rest take up at most `limit` bytes
+    ///
+    /// Returns how many cells were removed and how many bytes that freed.
+    pub fn prune(&self, limit: u64) -> anyhow::Result<(usize, u64)> {
+        let mut cells = Vec::new();
+        for entry in
+            fs::read_dir(&self.dir).with_context(|| format!("failed to read {:?}", self.dir))?
+        {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            let is_cell = entry
+                .path()
+                .extension()
+                .is_some_and(|extension| extension == "png");
+            if metadata.is_file() && is_cell {
+                cells.push((metadata.modified()?, metadata.len(), entry.path()));
+            }
+        }
+        // newest first, whatever doesn't fit after them goes
+        cells.sort_unstable_by_key(|(modified, ..)| Reverse(*modified));
+        let (mut kept, mut removed, mut freed) = (0, 0, 0);
+        for (_, size, path) in cells {
+            if kept + size <= limit {
+                kept += size;
+                continue;
+            }
+            fs::remove_file(&path).with_context(|| format!("failed to remove {:?}", path))?;
+            removed += 1;
+            freed += size;
+        }
+        Ok((removed, freed))
+    }
+
+    /// Identifies a build from everything that affects the image, the splashes' contents and
+    /// order included
+    pub fn fingerprint(
+        &self,
+        patch: &str,
+        splash_paths: &[PathBuf],
+        monitor: (u32, u32),
+        min_cell: (u32, u32),
+        options: BuildOptions,
+    ) -> anyhow::Result<String> {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(
+            format!(
+                "{BUILD_FORMAT}:{patch}:{}x{}:{}x{}:{}:{}:{:?}:{:?}",
+                monitor.0,
+                monitor.1,
+                min_cell.0,
+                min_cell.1,
+                options.quality,
+                options.memory_limit,
+                options.resize,
+                options.weights
+            )
+            .as_bytes(),
+        );
+        for path in splash_paths {
+            hasher.update(b":");
+            hasher.update(self.hash(path)?.as_bytes());
+        }
+        Ok(hasher.finalize().to_hex().to_string())
+    }
+}
+
+/// The fingerprint of every built tile, so builds with the same inputs can be skipped
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct BuildCache {
+    pub outputs: HashMap<PathBuf, String>,
+}
+
+impl Cached for BuildCache {
+    fn cache_name() -> String {
+        String::from("builds")
+    }
+
+    const LOCATION: Location = Location::Cache;
+}
+
+impl BuildCache {
+    pub fn read() -> anyhow::Result<Self> {
+        let mut builds = Self::default();
+        if builds.cache()?.exists() {
+            builds.load()?;
+        }
+        Ok(builds)
+    }
+
+    /// Whether the file at `path` was built from inputs with this `fingerprint`
+    pub fn is_current(&self, path: &Path, fingerprint: &str) -> bool {
+        path.exists()
+            && self
+                .outputs
+                .get(path)
+                .is_some_and(|built| built == fingerprint)
+    }
+
+    pub fn record(path: &Path, fingerprint: String) -> anyhow::Result<()> {
+        Self::default().transaction(|builds| {
+            builds.outputs.insert(path.to_path_buf(), fingerprint);
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        cell::Cell,
+        fs::{self, File},
+        path::PathBuf,
+        time::{Duration, SystemTime},
+    };
+
+    use image::DynamicImage;
+
+    use crate::{
+        cdragon::{fixtures, LATEST_PATCH},
+        paths,
+        resize::{ResizeFilter, ResizeOptions},
+        tiled_splash::BuildOptions,
+    };
+
+    use super::{BuildCache, CellCache};
+
+    fn splash(name: &str, color: u8) -> PathBuf {
+        fixtures::splash(name, (8, 6), [color, 0, 0])
+    }
+
+    #[test]
+    fn cells_are_reused() {
+        let cells = CellCache::open().unwrap();
+        let path = splash("1000.png", 10);
+        let made = Cell::new(0);
+        let make = || {
+            made.set(made.get() + 1);
+            Ok(DynamicImage::new_rgb8(4, 3))
+        };
+        let resize = ResizeOptions::default();
+        cells.cell(&path, (4, 3), (0, 0), resize, make).unwrap();
+        let cell = cells.cell(&path, (4, 3), (0, 0), resize, make).unwrap();
+        assert_eq!(made.get(), 1);
+        assert_eq!((cell.width(), cell.height()), (4, 3));
+        cells.cell(&path, (4, 3), (2, 0), resize, make).unwrap();
+        assert_eq!(made.get(), 2);
+        let nearest = ResizeOptions {
+            filter: ResizeFilter::Nearest,
+            ..resize
+        };
+        cells.cell(&path, (4, 3), (0, 0), nearest, make).unwrap();
+        assert_eq!(made.get(), 3);
+    }
+
+    #[test]
+    fn least_recently_used_cells_are_pruned() {
+        let cells = CellCache::open().unwrap();
+        let path = splash("1000.png", 10);
+        let resize = ResizeOptions::default();
+        let make = || Ok(DynamicImage::new_rgb8(4, 3));
+        for crop in [(0, 0), (2, 0), (0, 2)] {
+            cells.cell(&path, (4, 3), crop, resize, make).unwrap();
+        }
+        let cached: Vec<PathBuf> = fs::read_dir(&cells.dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        // each cell was last used a minute before the one before it
+        for (age, path) in cached.iter().enumerate() {
+            File::options()
+                .write(true)
+                .open(path)
+                .unwrap()
+                .set_modified(SystemTime::now() - Duration::from_secs(age as u64 * 60))
+                .unwrap();
+        }
+        let sizes: Vec<u64> = cached
+            .iter()
+            .map(|path| fs::metadata(path).unwrap().len())
+            .collect();
+        let (removed, freed) = cells.prune(sizes[0] + sizes[1]).unwrap();
+        assert_eq!((removed, freed), (1, sizes[2]));
+        assert!(cached[1].exists() && !cached[2].exists());
+        assert_eq!(cells.prune(0).unwrap().0, 2);
+    }
+
+    #[test]
+    fn fingerprints_follow_inputs() {
+        let cells = CellCache::open().unwrap();
+        let (annie, ahri) = (splash("1000.png", 10), splash("103000.png", 20));
+        let fingerprint = |paths: &[PathBuf], monitor| {
+            cells
+                .fingerprint(
+                    LATEST_PATCH,
+                    paths,
+                    monitor,
+                    (0, 0),
+                    BuildOptions::default(),
+                )
+                .unwrap()
+        };
+        let built = fingerprint(&[annie.clone(), ahri.clone()], (1920, 1080));
+        assert_eq!(
+            built,
+            fingerprint(&[annie.clone(), ahri.clone()], (1920, 1080))
+        );
+        assert_ne!(
+            built,
+            fingerprint(&[ahri.clone(), annie.clone()], (1920, 1080))
+        );
+        assert_ne!(
+            built,
+            fingerprint(&[annie.clone(), ahri.clone()], (2560, 1440))
+        );
+        let pinned = cells
+            .fingerprint(
+                "14.8",
+                &[annie.clone(), ahri.clone()],
+                (1920, 1080),
+                (0, 0),
+                BuildOptions::default(),
+            )
+            .unwrap();
+        assert_ne!(built, pinned);
+
+        let output = paths::get().unwrap().data.join("tile-0.jpg");
+        std::fs::write(&output, b"").unwrap();
+        BuildCache::record(&output, built.clone()).unwrap();
+        let builds = BuildCache::read().unwrap();
+        assert!(builds.is_current(&output, &built));
+        assert!(!builds.is_current(&output, "something else"));
+    }
+}
@@ -1,45 +1,226 @@
-use std::{any, fs, path::PathBuf};
+use std::{
+    any,
+    cell::RefCell,
+    collections::HashSet,
+    fs::{self, File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    process,
+};
 
-use anyhow::Context;
+use anyhow::{anyhow, Context};
+use chrono::Utc;
+use fs2::FileExt;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::paths::{self, Location};
+
+/// The field the layout version is stored under in every cache file
+const VERSION_KEY: &str = "version";
+
+thread_local! {
+    /// Caches this thread has a transaction open on
+    static HELD: RefCell<HashSet<PathBuf>> = RefCell::new(HashSet::new());
+}
+
+/// An exclusive advisory lock on a cache, released when dropped
+///
+/// The lock is taken on a `.lock` file next to the cache rather than the cache itself, since
+/// saving replaces the cache file.
+pub struct CacheLock {
+    file: File,
+    cache: PathBuf,
+}
+
+impl CacheLock {
+    /// Block until no other process holds the lock on `cache`
+    pub fn acquire(cache: &Path) -> anyhow::Result<Self> {
+        let lock_path = cache.with_extension("json.lock");
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&lock_path)
+            .with_context(|| format!("failed to open lock file {:?}", lock_path))?;
+        file.lock_exclusive()
+            .with_context(|| format!("failed to lock {:?}", lock_path))?;
+        Ok(Self {
+            file,
+            cache: cache.to_path_buf(),
+        })
+    }
+}
+
+impl Drop for CacheLock {
+    fn drop(&mut self) {
+        HELD.with(|held| held.borrow_mut().remove(&self.cache));
+        let _ = self.file.unlock();
+    }
+}
+
+fn in_transaction(cache: &Path) -> bool {
+    HELD.with(|held| held.borrow().contains(cache))
+}
+
+/// Replace `path` with `contents` so it's never left half written
+pub fn write_atomic(path: &Path, contents: &str) -> anyhow::Result<()> {
+    write_atomic_with(path, |file| Ok(file.write_all(contents.as_bytes())?))
+}
+
+/// Replace `path` with whatever `write` writes, so it's never left half written
+///
+/// `write` gets a temporary file in the same directory that's renamed over `path` once it's
+/// done, and removed instead if it fails.
+pub fn write_atomic_with(
+    path: &Path,
+    write: impl FnOnce(&mut File) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    let file_name = path
+        .file_name()
+        .ok_or(anyhow!("can't write to {:?}", path))?
+        .to_string_lossy();
+    let temp = path.with_file_name(format!(".{}.{}.tmp", file_name, process::id()));
+    let write = || -> anyhow::Result<()> {
+        let mut file = File::create(&temp)?;
+        write(&mut file)?;
+        file.sync_all()?;
+        fs::rename(&temp, path)?;
+        Ok(())
+    };
+    write().map_err(|err| {
+        let _ = fs::remove_file(&temp);
+        err.context(format!("failed to write {:?}", path))
+    })
+}
 
 pub trait Cached: Serialize + for<'a> Deserialize<'a> + Default {
     fn cache_name() -> String;
 
+    /// Which of the [`paths`] directories the file is kept in
+    const LOCATION: Location = Location::Data;
+
+    /// The layout version written with the cache, bumped whenever older files need migrating
+    ///
+    /// Files written before versioning existed are version 0.
+    const VERSION: u32 = 1;
+
+    /// Upgrade the raw json of a version `from` cache to version `from + 1`
+    fn migrate(value: Value, from: u32) -> anyhow::Result<Value> {
+        let _ = from;
+        Ok(value)
+    }
+
+    /// Atomically write the cache, unless a transaction on it is open, which saves once it's done
     fn save(&self) -> anyhow::Result<()> {
-        let cache_str = serde_json::to_string_pretty(&self)?;
-        fs::write(self.cache()?, cache_str)?;
-        Ok(())
+        let cache = self.cache()?;
+        if in_transaction(&cache) {
+            return Ok(());
+        }
+        let _lock = CacheLock::acquire(&cache)?;
+        write_atomic(&cache, &self.to_cached_string()?)
     }
 
+    /// Run `f` with the cache locked, then save it once
+    ///
+    /// `self` is reloaded first, so changes other processes saved since it was loaded aren't
+    /// overwritten. Saves inside `f` are deferred to the end and nested transactions join the
+    /// outer one. Nothing is saved if `f` fails.
+    fn transaction<R>(
+        &mut self,
+        f: impl FnOnce(&mut Self) -> anyhow::Result<R>,
+    ) -> anyhow::Result<R> {
+        let cache = self.cache()?;
+        if in_transaction(&cache) {
+            return f(self);
+        }
+        let _lock = CacheLock::acquire(&cache)?;
+        HELD.with(|held| held.borrow_mut().insert(cache.clone()));
+        if cache.exists() {
+            self.load()?;
+        }
+        let result = f(self)?;
+        write_atomic(&cache, &self.to_cached_string()?)?;
+        Ok(result)
+    }
+
+    /// Load the cache, migrating it from older layouts
+    ///
+    /// If the file exists but can't be read as `Self`, it's copied to a backup next to it
+    /// before the error is returned, so it isn't lost when the caller starts over.
     fn load(&mut self) -> anyhow::Result<()> {
-        let cached_data: String = fs::read_to_string(self.cache()?)
-            .with_context(|| format!("failed to read from {:?}", self.cache()))?
-            .parse()
-            .with_context(|| {
+        let cache = self.cache()?;
+        let cached_data = fs::read_to_string(&cache)
+            .with_context(|| format!("failed to read from {:?}", cache))?;
+
+        match Self::from_cached_str(&cached_data) {
+            Ok(loaded) => {
+                *self = loaded;
+                Ok(())
+            }
+            Err(err) => {
+                let backup = cache.with_file_name(format!(
+                    "{}.json.{}.bak",
+                    Self::cache_name(),
+                    Utc::now().format("%Y%m%d%H%M%S")
+                ));
+                fs::copy(&cache, &backup)
+                    .with_context(|| format!("failed to back up {:?}", cache))?;
+                Err(err.context(format!(
+                    "the unreadable cache was backed up to {:?}",
+                    backup
+                )))
+            }
+        }
+    }
+
+    fn to_cached_string(&self) -> anyhow::Result<String> {
+        let mut value = serde_json::to_value(self)?;
+        if let Value::Object(map) = &mut value {
+            map.insert(VERSION_KEY.to_string(), Self::VERSION.into());
+        }
+        Ok(serde_json::to_string_pretty(&value)?)
+    }
+
+    fn from_cached_str(cached_data: &str) -> anyhow::Result<Self> {
+        let mut value: Value = serde_json::from_str(cached_data).with_context(|| {
+            format!(
+                "failed to parse the cached json for type: {:?}",
+                any::type_name::<Self>()
+            )
+        })?;
+        let mut version = match value.get(VERSION_KEY) {
+            Some(version) => version
+                .as_u64()
+                .and_then(|version| u32::try_from(version).ok())
+                .ok_or(anyhow!("invalid cache version {version}"))?,
+            None => 0,
+        };
+        if version > Self::VERSION {
+            return Err(anyhow!(
+                "the cache is version {version}, but this version of rusty-splash only reads up to version {}",
+                Self::VERSION
+            ));
+        }
+        while version < Self::VERSION {
+            value = Self::migrate(value, version).with_context(|| {
                 format!(
-                    "failed to parse the deserialized type: {:?}",
+                    "failed to migrate {:?} from version {version}",
                     any::type_name::<Self>()
                 )
             })?;
+            version += 1;
+        }
+        if let Value::Object(map) = &mut value {
+            map.remove(VERSION_KEY);
+        }
 
-        *self = serde_json::from_str(&cached_data.to_owned()).with_context(|| {
-            format!("failed to deserialize type: {:?}", any::type_name::<Self>())
-        })?;
-
-        Ok(())
+        serde_json::from_value(value)
+            .with_context(|| format!("failed to deserialize type: {:?}", any::type_name::<Self>()))
     }
 
     fn cache(&self) -> anyhow::Result<PathBuf> {
-        let home = dirs::home_dir().with_context(|| "Couldn't get home dir")?;
-        let mut file_path = [home.to_str().unwrap(), "rusty-splash"]
-            .iter()
-            .collect::<PathBuf>();
-        if !file_path.exists() {
-            fs::create_dir_all(&file_path)
-                .with_context(|| format!("failed to create dir path: {:?}", file_path))?;
-        }
-        file_path.push(format!("{}.json", Self::cache_name()));
-        Ok(file_path)
+        let dir = paths::get()?.ensure(Self::LOCATION)?;
+        Ok(dir.join(format!("{}.json", Self::cache_name())))
     }
 }
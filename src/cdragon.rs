@@ -1,47 +1,263 @@
 use anyhow::{anyhow, Context};
 use chrono::{DateTime, Utc};
-use core::panic;
+use clap::{Args, ValueEnum};
 use rayon::iter::{IndexedParallelIterator, IntoParallelRefIterator, ParallelIterator};
+use reqwest::header::{CONTENT_LENGTH, ETAG, LAST_MODIFIED};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use std::{collections::HashMap, fs, io, path::PathBuf, u32, u64};
+use std::{
+    collections::HashMap,
+    fmt, fs,
+    path::{Path, PathBuf},
+};
 
-use crate::cache::Cached;
+use crate::{
+    cache::Cached,
+    changelog::{Changelog, ChangelogEntry},
+    config::{Config, OfflinePolicy},
+    datadragon::DataDragon,
+    paths::{self, Location},
+};
 
-#[derive(Debug, Default, Serialize, Deserialize)]
+/// The CommunityDragon locale everything is fetched in unless configured otherwise
+pub const DEFAULT_LOCALE: &str = "default";
+
+/// The patch that always has the newest skins
+pub const LATEST_PATCH: &str = "latest";
+
+const CDRAGON_URL: &str = "https://raw.communitydragon.org";
+
+#[derive(Debug, Serialize, Deserialize)]
 pub struct CDragon {
-    pub latest_date: DateTime<Utc>,
     pub champions: HashMap<u64, Champion>,
-    pub plugins: Vec<Plugin>,
+    /// The locale the champion and skin names are in
+    #[serde(default = "default_locale")]
+    pub locale: String,
+    /// Where the champions were fetched from
+    #[serde(default)]
+    pub source: SourceKind,
+    /// The source's version when the champions were fetched, see [`SplashSource::version`]
+    #[serde(default)]
+    pub version: String,
+    /// The game patch the skins are from, like `14.8`, or [`LATEST_PATCH`]
+    #[serde(default = "latest_patch")]
+    pub patch: String,
+}
+
+impl Default for CDragon {
+    fn default() -> Self {
+        Self {
+            champions: HashMap::new(),
+            locale: default_locale(),
+            source: SourceKind::default(),
+            version: String::new(),
+            patch: latest_patch(),
+        }
+    }
+}
+
+fn default_locale() -> String {
+    DEFAULT_LOCALE.to_string()
+}
+
+fn latest_patch() -> String {
+    LATEST_PATCH.to_string()
+}
+
+fn game_data_url(patch: &str) -> String {
+    format!("{CDRAGON_URL}/{patch}/plugins/rcp-be-lol-game-data/global")
+}
+
+/// Which art is downloaded for a skin
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum ArtKind {
+    /// The full splash art
+    #[default]
+    Uncentered,
+    /// The splash cropped around the champion, as shown in the client
+    Centered,
+}
+
+/// Somewhere skin data and splash art can be fetched from
+pub trait SplashSource {
+    /// A version that changes whenever the skin data does
+    fn version(&self) -> anyhow::Result<String>;
+    /// Every champion and their skins, named in `locale`
+    fn champions(&self, locale: &str) -> anyhow::Result<HashMap<u64, Champion>>;
+    /// Where the art for one of `champion`'s skins is downloaded from
+    fn art_url(&self, champion: &Champion, skin: &Skin, kind: ArtKind) -> String;
+}
+
+/// The sources [`CDragon`] can fetch from
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum SourceKind {
+    /// CommunityDragon, the only source with skin lines and rarities
+    #[default]
+    CommunityDragon,
+    /// Riot's Data Dragon
+    DataDragon,
+}
+
+impl SourceKind {
+    /// The source, pinned to `patch`
+    pub fn source(self, patch: &str) -> Box<dyn SplashSource> {
+        match self {
+            SourceKind::CommunityDragon => Box::new(CDragon {
+                patch: patch.to_string(),
+                ..CDragon::default()
+            }),
+            SourceKind::DataDragon => Box::new(DataDragon::pinned(patch)),
+        }
+    }
+}
+
+impl fmt::Display for SourceKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SourceKind::CommunityDragon => write!(f, "CommunityDragon"),
+            SourceKind::DataDragon => write!(f, "Data Dragon"),
+        }
+    }
 }
 
 impl Cached for CDragon {
     fn cache_name() -> String {
         String::from("cdragon")
     }
+
+    const LOCATION: Location = Location::Cache;
+
+    /// Each pinned patch is cached separately, next to the latest skins
+    fn cache(&self) -> anyhow::Result<PathBuf> {
+        let dir = paths::get()?.ensure(Self::LOCATION)?;
+        Ok(match self.patch.as_str() {
+            LATEST_PATCH => dir.join(format!("{}.json", Self::cache_name())),
+            patch => dir.join(format!("{}-{patch}.json", Self::cache_name())),
+        })
+    }
+}
+
+impl SplashSource for CDragon {
+    /// When the game data plugin was last modified, old patches never change so they're their
+    /// own version
+    fn version(&self) -> anyhow::Result<String> {
+        if self.patch != LATEST_PATCH {
+            return Ok(self.patch.clone());
+        }
+        Ok(Self::get_plugins()?
+            .iter()
+            .find(|plugin| plugin.name == PluginName::RcpBeLolGameData)
+            .ok_or(anyhow!(
+                "couldn't find lol game data plugin in the available plugins"
+            ))?
+            .mtime
+            .to_rfc3339())
+    }
+
+    fn champions(&self, locale: &str) -> anyhow::Result<HashMap<u64, Champion>> {
+        Self::get_champions(locale, &self.patch)
+    }
+
+    fn art_url(&self, _champion: &Champion, skin: &Skin, kind: ArtKind) -> String {
+        format!(
+            "{}/{DEFAULT_LOCALE}/{}",
+            game_data_url(&self.patch),
+            skin.art_path(kind).display()
+        )
+    }
 }
 
 impl CDragon {
-    pub fn new() -> anyhow::Result<Self> {
-        let mut cdragon = CDragon::default();
-        match cdragon.load() {
-            Ok(_) => match CDragon::up_to_date(&cdragon.latest_date) {
-                Ok(_) => Ok(cdragon),
-                Err(err) => {
-                    println!("{err}");
-                    cdragon.update()?;
-                    cdragon.save()?;
-                    Ok(cdragon)
+    /// Load the cached skins, updating them from the configured sources as the offline policy
+    /// allows
+    ///
+    /// Sources are tried in order, the cache is refetched when it's for a different locale or
+    /// came from another source. Every patch has its own cache. Skins cached from the primary
+    /// source are used before the fallback is tried, and the fallback's are never saved over them.
+    pub fn new(config: &Config) -> anyhow::Result<Self> {
+        let (locale, offline) = (config.art.locale.as_str(), config.download.offline);
+        let mut cdragon = CDragon {
+            patch: config.source.patch.clone(),
+            ..CDragon::default()
+        };
+        let loaded = cdragon.load();
+        let cached = loaded.is_ok() && cdragon.locale == locale;
+        if offline == OfflinePolicy::Offline {
+            return match (cached, loaded) {
+                (true, _) => Ok(cdragon),
+                (false, Err(err)) => Err(err.context(format!(
+                    "offline, and there are no cached skins for patch {}",
+                    cdragon.patch
+                ))),
+                (false, Ok(_)) => Err(anyhow!(
+                    "offline, and the cached skins are for locale {}, not {locale}",
+                    cdragon.locale
+                )),
+            };
+        }
+        if let (false, Err(err)) = (cached, loaded) {
+            eprintln!("{err}");
+        }
+        if !cached {
+            cdragon.version.clear();
+            cdragon.locale = locale.to_string();
+        }
+        // changes are only worth recording against skins from the same source and locale
+        let previous = cached.then_some(cdragon.source);
+        // a fallback has less to say about skins, so it never replaces the primary's cache
+        let primary_cached = previous == Some(config.source.primary);
+        let mut errors = Vec::new();
+        for (index, kind) in config.source.order().into_iter().enumerate() {
+            let fallback = index > 0;
+            if fallback && primary_cached && offline == OfflinePolicy::Auto {
+                break;
+            }
+            match cdragon.update(kind) {
+                Ok(Some(entry)) => {
+                    if !(fallback && primary_cached) {
+                        cdragon.save()?;
+                    }
+                    if previous == Some(kind) && !entry.is_empty() {
+                        eprintln!(
+                            "skins updated: {}, run `whats-new` to see them",
+                            entry.summary()
+                        );
+                        Changelog::record(entry)?;
+                    }
+                    return Ok(cdragon);
                 }
-            },
-            Err(err) => {
-                println!("{err}");
-                cdragon.update()?;
-                cdragon.save()?;
+                Ok(None) => return Ok(cdragon),
+                Err(err) => errors.push(format!("{err:#}")),
+            }
+        }
+        let err = anyhow!(errors.join(", "));
+        match cached && offline == OfflinePolicy::Auto {
+            true => {
+                eprintln!("couldn't check for new skins, using the cached ones: {err}");
                 Ok(cdragon)
             }
+            false => Err(err),
         }
     }
+
+    /// The skins from another patch, with the rest of `config` unchanged
+    pub fn at_patch(config: &Config, patch: &str) -> anyhow::Result<Self> {
+        let mut config = config.clone();
+        config.source.patch = patch.to_string();
+        Self::new(&config)
+    }
+
+    /// Every numbered patch on CommunityDragon, newest first
+    pub fn patches() -> anyhow::Result<Vec<String>> {
+        let listing = fetch(format!("{CDRAGON_URL}/json/"))
+            .and_then(|response| response.error_for_status())
+            .and_then(|response| response.text())
+            .with_context(|| "error fetching patches")?;
+        parse_patches(&listing)
+    }
+
     pub fn up_to_date(current_update_timestamp: &DateTime<Utc>) -> anyhow::Result<()> {
         let plugins = Self::get_plugins()?;
 
@@ -56,46 +272,57 @@ impl CDragon {
         found_newer_version.ok_or(anyhow!("update available"))
     }
 
-    pub fn update(&mut self) -> anyhow::Result<()> {
-        let plugins = Self::get_plugins()?;
-        let champions = Self::get_champions()?;
-        let latest_date = plugins
-            .iter()
-            .find(|plugin| plugin.name == PluginName::RcpBeLolGameData)
-            .ok_or(anyhow!(
-                "couldn't find lol game data plugin in the available plugins"
-            ))?
-            .mtime;
-
-        *self = CDragon {
-            plugins,
-            champions,
-            latest_date,
+    /// Fetch the champions from `kind` unless they already came from its current version
+    ///
+    /// Returns what changed, or `None` when nothing was fetched.
+    pub fn update(&mut self, kind: SourceKind) -> anyhow::Result<Option<ChangelogEntry>> {
+        let source = kind.source(&self.patch);
+        let version = source
+            .version()
+            .with_context(|| format!("couldn't check {kind} for new skins"))?;
+        if self.source == kind && self.version == version {
+            return Ok(None);
+        }
+        let champions = source
+            .champions(&self.locale)
+            .with_context(|| format!("couldn't fetch skins from {kind}"))?;
+        let entry = ChangelogEntry {
+            from_version: self.version.clone(),
+            to_version: version.clone(),
+            ..ChangelogEntry::between(&self.champions, &champions)
         };
-        Ok(())
+        self.champions = champions;
+        self.source = kind;
+        self.version = version;
+        Ok(Some(entry))
     }
 
-    fn fetch_plugins() -> String {
+    fn fetch_plugins() -> anyhow::Result<String> {
         fetch("https://raw.communitydragon.org/json/latest/plugins/".to_string())
-            .unwrap_or_else(|err| panic!("error fetching plugins: {err}"))
+            .with_context(|| "error fetching plugins")?
             .text()
-            .unwrap_or_else(|err| panic!("error in plugin response text: {err}"))
+            .with_context(|| "error in plugin response text")
     }
 
     fn get_plugins() -> anyhow::Result<Vec<Plugin>> {
-        let plugin_res = Self::fetch_plugins();
+        let plugin_res = Self::fetch_plugins()?;
         serde_json::from_str(&plugin_res).with_context(|| "failed to deserialize plugins")
     }
 
-    fn fetch_champions() -> anyhow::Result<String> {
-        fetch("https://raw.communitydragon.org/latest/plugins/rcp-be-lol-game-data/global/default/v1/champion-summary.json".to_string())
-        .with_context(|| format!("error fetching champions"))?
-            .text()
-            .with_context(|| format!("error in champions response text"))
+    fn fetch_champions(locale: &str, patch: &str) -> anyhow::Result<String> {
+        fetch(format!(
+            "{}/{locale}/v1/champion-summary.json",
+            game_data_url(patch)
+        ))
+        .and_then(|response| response.error_for_status())
+        .with_context(|| format!("error fetching champions for patch {patch}"))?
+        .text()
+        .with_context(|| "error in champions response text")
     }
 
-    fn get_champions() -> anyhow::Result<HashMap<u64, Champion>> {
-        let champ_res = Self::fetch_champions().with_context(|| "failed to fetch champions")?;
+    fn get_champions(locale: &str, patch: &str) -> anyhow::Result<HashMap<u64, Champion>> {
+        let champ_res =
+            Self::fetch_champions(locale, patch).with_context(|| "failed to fetch champions")?;
         let data: Value = serde_json::from_str(&champ_res)
             .with_context(|| "failed to deserialize champions response")?;
         let champions: anyhow::Result<HashMap<u64, Champion>> = data
@@ -119,7 +346,7 @@ impl CDragon {
                     .with_context(|| "failed to get alias for champion")?
                     .to_string()
                     .replace('\"', "");
-                let skins = Self::get_skins(id)?;
+                let skins = Self::get_skins(id, locale, patch)?;
                 Ok((
                     id,
                     Champion {
@@ -134,15 +361,22 @@ impl CDragon {
         champions
     }
 
-    pub fn fetch_champion(id: u64) -> anyhow::Result<String> {
-        fetch(format!("https://raw.communitydragon.org/latest/plugins/rcp-be-lol-game-data/global/default/v1/champions/{}.json", id).to_string())
-            .with_context(|| "error fetching champion")?
-            .text()
-            .with_context(|| "error in champion response text")
+    pub fn fetch_champion(id: u64, locale: &str, patch: &str) -> anyhow::Result<String> {
+        fetch(format!(
+            "{}/{locale}/v1/champions/{id}.json",
+            game_data_url(patch)
+        ))
+        .with_context(|| "error fetching champion")?
+        .text()
+        .with_context(|| "error in champion response text")
     }
 
-    pub fn get_skins(champion_id: u64) -> anyhow::Result<HashMap<u64, Skin>> {
-        let champ_res = Self::fetch_champion(champion_id)?;
+    pub fn get_skins(
+        champion_id: u64,
+        locale: &str,
+        patch: &str,
+    ) -> anyhow::Result<HashMap<u64, Skin>> {
+        let champ_res = Self::fetch_champion(champion_id, locale, patch)?;
         let data: Value = serde_json::from_str(&champ_res)
             .with_context(|| "failed to convert the response text to a Json value")?;
         data.as_object()
@@ -160,16 +394,7 @@ impl CDragon {
                     .with_context(|| "failed to cast id for skin to u64")?;
                 let mut skin: Skin = serde_json::from_value(value.to_owned())
                     .with_context(|| "failed to deserialize skin json")?;
-                skin.uncentered_splash_path = skin
-                    .uncentered_splash_path
-                    .clone()
-                    .components()
-                    .skip(3)
-                    .map(|component| {
-                        let thing = component.as_os_str();
-                        thing.to_ascii_lowercase()
-                    })
-                    .collect();
+                skin.uncentered_splash_path = asset_path(&skin.uncentered_splash_path);
                 Ok((id, skin))
             })
             .collect()
@@ -209,7 +434,7 @@ impl CDragon {
         }
     }
 
-    pub fn query(&mut self, query: impl Into<String>) -> anyhow::Result<Vec<&Skin>> {
+    pub fn query(&self, query: impl Into<String>) -> anyhow::Result<Vec<&Skin>> {
         let query: String = query.into();
         let result: Vec<&Skin> = self
             .champions
@@ -230,39 +455,222 @@ impl CDragon {
         }
     }
 
-    /// Download an uncentered splash
+    /// Skins that aren't in `older`, ordered by id
+    pub fn new_skins(&self, older: &CDragon) -> Vec<&Skin> {
+        let mut skins: Vec<&Skin> = self
+            .champions
+            .values()
+            .flat_map(|champion| champion.skins.values())
+            .filter(|skin| older.skin(skin.id).is_none())
+            .collect();
+        skins.sort_by_key(|skin| skin.id);
+        skins
+    }
+
+    /// Every skin matching all of the query's filters, ordered by id
+    pub fn search(&self, query: &SkinQuery) -> anyhow::Result<Vec<&Skin>> {
+        if query.is_empty() {
+            return Err(anyhow!("the query needs at least one filter"));
+        }
+        let mut result: Vec<&Skin> = self
+            .champions
+            .par_iter()
+            .flat_map(|champ| {
+                champ
+                    .1
+                    .skins
+                    .par_iter()
+                    .filter(|skin| query.matches(champ.1, skin.1))
+                    .map(|skin| skin.1)
+            })
+            .collect();
+        result.sort_by_key(|skin| skin.id);
+        Ok(result)
+    }
+
+    /// Where `source` has the art for `skin`
+    pub fn art_url(
+        &self,
+        skin: &Skin,
+        kind: ArtKind,
+        source: SourceKind,
+    ) -> anyhow::Result<String> {
+        let champion = self
+            .champions
+            .get(&skin.champion_id())
+            .ok_or(anyhow!("no champion found for skin {}", skin.id))?;
+        Ok(source.source(&self.patch).art_url(champion, skin, kind))
+    }
+
+    /// Download a skin's splash art, trying each of `sources` until one has it
+    ///
+    /// Returns what the server said about the file, so later changes to it can be spotted.
     ///
     /// # Arguments
     /// * `save_path` - the target directory
-    pub fn download_splash(skin: &Skin, save_path: &PathBuf) -> anyhow::Result<()> {
-        let mut file_path = save_path.clone();
-        file_path.push(format!("{}.jpg", skin.id.to_string()));
-        let mut file = fs::File::create(&file_path)
-            .with_context(|| format!("error creating file for skin {}", skin.id))?;
-
-        let url = PathBuf::from(
-            "https://raw.communitydragon.org/latest/plugins/rcp-be-lol-game-data/global/default",
-        )
-        .join(&skin.uncentered_splash_path);
-        dbg!(&url);
-        let image = fetch(url.to_str().unwrap())
-            .with_context(|| format!("error fetching skin {}", skin.id))?
-            .bytes()
-            .with_context(|| format!("error in reponse bytes for skin {}", skin.id))?;
+    pub fn download_splash(
+        &self,
+        skin: &Skin,
+        save_path: &Path,
+        kind: ArtKind,
+        sources: &[SourceKind],
+    ) -> anyhow::Result<RemoteSplash> {
+        let mut errors = Vec::new();
+        for source in sources {
+            let url = self.art_url(skin, kind, *source)?;
+            let image = fetch(url.clone())
+                .and_then(|response| response.error_for_status())
+                .and_then(|response| {
+                    let remote = RemoteSplash::from_response(url, &response);
+                    Ok((remote, response.bytes()?))
+                });
+            match image {
+                Ok((remote, image)) => {
+                    let file_path = save_path.join(format!("{}.jpg", skin.id));
+                    fs::write(&file_path, &image)
+                        .with_context(|| format!("error saving image {:?}", file_path))?;
+                    return Ok(RemoteSplash {
+                        size: Some(image.len() as u64),
+                        ..remote
+                    });
+                }
+                Err(err) => errors.push(format!("{source}: {err}")),
+            }
+        }
+        Err(anyhow!(
+            "error fetching skin {}: {}",
+            skin.id,
+            errors.join(", ")
+        ))
+    }
+}
 
-        io::copy(&mut image.as_ref(), &mut file)
-            .with_context(|| format!("error saving image {:?}", file_path))?;
+/// What the server said about a splash, kept to spot art that's been repainted since
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RemoteSplash {
+    pub url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub etag: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_modified: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+}
 
-        Ok(())
+impl RemoteSplash {
+    fn from_response(url: String, response: &reqwest::blocking::Response) -> Self {
+        let header = |name| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(String::from)
+        };
+        Self {
+            url,
+            etag: header(ETAG),
+            last_modified: header(LAST_MODIFIED),
+            size: header(CONTENT_LENGTH).and_then(|size| size.parse().ok()),
+        }
+    }
+
+    /// Ask the server about the splash at `url` without downloading it
+    pub fn head(url: &str) -> anyhow::Result<Self> {
+        let response = reqwest::blocking::Client::new()
+            .head(url)
+            .send()
+            .and_then(|response| response.error_for_status())
+            .with_context(|| format!("error checking {url}"))?;
+        Ok(Self::from_response(url.to_string(), &response))
+    }
+
+    /// Whether `latest` is different art, going by the ETag, then Last-Modified, then the size
+    ///
+    /// Anything the server didn't send both times is skipped, so with nothing to compare it's
+    /// assumed to be the same.
+    pub fn changed(&self, latest: &RemoteSplash) -> bool {
+        if let (Some(etag), Some(latest)) = (&self.etag, &latest.etag) {
+            return etag != latest;
+        }
+        if let (Some(modified), Some(latest)) = (&self.last_modified, &latest.last_modified) {
+            return modified != latest;
+        }
+        matches!((self.size, latest.size), (Some(size), Some(latest)) if size != latest)
     }
 }
 
 #[derive(Debug, Default, Serialize, Deserialize)]
 pub struct Champion {
-    id: u64,
-    name: String,
-    alias: String,
-    skins: HashMap<u64, Skin>,
+    pub id: u64,
+    pub name: String,
+    pub alias: String,
+    pub skins: HashMap<u64, Skin>,
+}
+
+/// Filters for finding skins, every filter that's set has to match
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, Args)]
+pub struct SkinQuery {
+    /// The champion's name or alias
+    #[arg(long)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub champion: Option<String>,
+    /// Part of the name of one of the skin's skin lines
+    #[arg(long)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub skin_line: Option<String>,
+    /// The skin's rarity, like epic or legendary
+    #[arg(long)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rarity: Option<String>,
+    /// Part of the skin's name
+    #[arg(long = "name", id = "name_filter", value_name = "NAME")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+}
+
+impl SkinQuery {
+    pub fn is_empty(&self) -> bool {
+        self.champion.is_none()
+            && self.skin_line.is_none()
+            && self.rarity.is_none()
+            && self.name.is_none()
+    }
+
+    pub fn matches(&self, champion: &Champion, skin: &Skin) -> bool {
+        let contains =
+            |haystack: &str, needle: &str| haystack.to_lowercase().contains(&needle.to_lowercase());
+        self.champion.as_ref().is_none_or(|name| {
+            champion.name.eq_ignore_ascii_case(name) || champion.alias.eq_ignore_ascii_case(name)
+        }) && self.skin_line.as_ref().is_none_or(|line| {
+            skin.skin_lines
+                .iter()
+                .flatten()
+                .any(|skin_line| contains(&skin_line.name, line))
+        }) && self.rarity.as_ref().is_none_or(|rarity| {
+            // CommunityDragon prefixes rarities with a k, like kEpic
+            let skin_rarity = skin.rarity.strip_prefix('k').unwrap_or(&skin.rarity);
+            let rarity = rarity.strip_prefix('k').unwrap_or(rarity);
+            skin_rarity.eq_ignore_ascii_case(rarity)
+        }) && self
+            .name
+            .as_ref()
+            .is_none_or(|name| contains(&skin.name, name))
+    }
+}
+
+impl fmt::Display for SkinQuery {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let filters: Vec<String> = [
+            ("champion", &self.champion),
+            ("skin line", &self.skin_line),
+            ("rarity", &self.rarity),
+            ("name", &self.name),
+        ]
+        .iter()
+        .filter_map(|(label, value)| value.as_ref().map(|value| format!("{label}: {value}")))
+        .collect();
+        write!(f, "{}", filters.join(", "))
+    }
 }
 
 #[derive(Debug, Default, Serialize, Deserialize)]
@@ -284,11 +692,34 @@ pub struct Skin {
     pub skin_lines: Option<Vec<SkinLine>>,
 }
 
+impl Skin {
+    /// The path of the skin's art on CommunityDragon
+    ///
+    /// Skins from Data Dragon don't have paths, so they're worked out from the ids.
+    pub fn art_path(&self, kind: ArtKind) -> PathBuf {
+        let champion = self.champion_id();
+        match kind {
+            ArtKind::Uncentered if self.uncentered_splash_path.as_os_str().is_empty() => {
+                format!("v1/champion-splashes/uncentered/{champion}/{}.jpg", self.id).into()
+            }
+            ArtKind::Uncentered => self.uncentered_splash_path.clone(),
+            ArtKind::Centered if self.splash_path.is_empty() => {
+                format!("v1/champion-splashes/{champion}/{}.jpg", self.id).into()
+            }
+            ArtKind::Centered => asset_path(Path::new(&self.splash_path)),
+        }
+    }
+    /// Skin ids are the champion id followed by a three digit skin number
+    pub fn champion_id(&self) -> u64 {
+        self.id / 1000
+    }
+}
+
 #[derive(Debug, Default, Serialize, Deserialize)]
-struct SkinLine {
-    id: u32,
+pub struct SkinLine {
+    pub id: u32,
     #[serde(default)]
-    name: String,
+    pub name: String,
 }
 
 #[derive(Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
@@ -358,7 +789,7 @@ mod mtime_format {
     use chrono::{DateTime, NaiveDateTime, Utc};
     use serde::{self, Deserialize, Deserializer, Serializer};
 
-    const FORMAT: &'static str = "%a, %d %b %Y %H:%M:%S %Z";
+    const FORMAT: &str = "%a, %d %b %Y %H:%M:%S %Z";
 
     // The signature of a serialize_with function must follow the pattern:
     //
@@ -391,17 +822,140 @@ mod mtime_format {
         Ok(DateTime::<Utc>::from_naive_utc_and_offset(dt, Utc))
     }
 }
+/// Turn an asset path from the game data, like `/lol-game-data/assets/v1/champion-splashes/1/1000.jpg`,
+/// into its path on CommunityDragon, like `v1/champion-splashes/1/1000.jpg`
+fn asset_path(path: &Path) -> PathBuf {
+    path.components()
+        .skip(3)
+        .map(|component| component.as_os_str().to_ascii_lowercase())
+        .collect()
+}
+
+/// Pick the numbered patches, like `14.8`, out of CommunityDragon's directory listing
+fn parse_patches(listing: &str) -> anyhow::Result<Vec<String>> {
+    #[derive(Deserialize)]
+    struct Entry {
+        name: String,
+    }
+    let entries: Vec<Entry> =
+        serde_json::from_str(listing).with_context(|| "failed to deserialize patches")?;
+    let mut patches: Vec<(u32, u32, String)> = entries
+        .into_iter()
+        .filter_map(|entry| {
+            let (major, minor) = entry.name.split_once('.')?;
+            Some((major.parse().ok()?, minor.parse().ok()?, entry.name))
+        })
+        .collect();
+    patches.sort_unstable_by(|a, b| b.cmp(a));
+    Ok(patches.into_iter().map(|(_, _, name)| name).collect())
+}
+
 pub fn fetch(url: impl Into<String>) -> reqwest::Result<reqwest::blocking::Response> {
     reqwest::blocking::get(url.into())
 }
 
+/// Skin data for tests that can't reach CommunityDragon
+#[cfg(test)]
+pub(crate) mod fixtures {
+    use std::{collections::HashMap, fs, path::PathBuf};
+
+    use image::{Rgb, RgbImage};
+
+    use super::{CDragon, Champion, Skin, SkinLine};
+    use crate::paths;
+
+    /// A splash of one `color` saved at `name` in the test's own data directory
+    pub fn splash(name: &str, size: (u32, u32), color: [u8; 3]) -> PathBuf {
+        let path = paths::get().unwrap().data.join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        RgbImage::from_pixel(size.0, size.1, Rgb(color))
+            .save(&path)
+            .unwrap();
+        path
+    }
+
+    pub fn skin(id: u64, name: &str, skin_line: Option<&str>) -> Skin {
+        Skin {
+            id,
+            name: name.to_string(),
+            is_base: id.is_multiple_of(1000),
+            splash_path: format!(
+                "/lol-game-data/assets/v1/champion-splashes/{}/{id}.jpg",
+                id / 1000
+            ),
+            uncentered_splash_path: format!(
+                "v1/champion-splashes/uncentered/{}/{id}.jpg",
+                id / 1000
+            )
+            .into(),
+            skin_type: String::new(),
+            rarity: match skin_line {
+                Some(_) => String::from("kEpic"),
+                None => String::from("kNoRarity"),
+            },
+            is_legacy: false,
+            skin_lines: skin_line.map(|name| {
+                vec![SkinLine {
+                    id: 1,
+                    name: name.to_string(),
+                }]
+            }),
+        }
+    }
+
+    pub fn champion(id: u64, name: &str, skins: Vec<Skin>) -> (u64, Champion) {
+        (
+            id,
+            Champion {
+                id,
+                name: name.to_string(),
+                alias: name.to_string(),
+                skins: skins.into_iter().map(|skin| (skin.id, skin)).collect(),
+            },
+        )
+    }
+
+    pub fn cdragon() -> CDragon {
+        CDragon {
+            champions: HashMap::from([
+                champion(
+                    1,
+                    "Annie",
+                    vec![skin(1000, "Annie", None), skin(1001, "Goth Annie", None)],
+                ),
+                champion(
+                    103,
+                    "Ahri",
+                    vec![
+                        skin(103000, "Ahri", None),
+                        skin(103015, "Star Guardian Ahri", Some("Star Guardian")),
+                    ],
+                ),
+                champion(
+                    222,
+                    "Jinx",
+                    vec![
+                        skin(222000, "Jinx", None),
+                        skin(222020, "Star Guardian Jinx", Some("Star Guardian")),
+                    ],
+                ),
+            ]),
+            ..CDragon::default()
+        }
+    }
+}
+
 #[cfg(test)]
+#[allow(clippy::len_zero)]
 mod test {
     use chrono::{TimeZone, Utc};
 
     use crate::cdragon::CDragon;
 
-    use super::PluginName;
+    use super::{
+        fixtures, parse_patches, ArtKind, Cached, PluginName, RemoteSplash, Skin, SkinQuery,
+        DEFAULT_LOCALE, LATEST_PATCH,
+    };
 
     #[test]
     fn get_plugins() {
@@ -412,10 +966,110 @@ mod test {
 
     #[test]
     fn get_champions() {
-        let champions = CDragon::get_champions();
+        let champions = CDragon::get_champions(DEFAULT_LOCALE, LATEST_PATCH);
         assert!(champions.unwrap().len() > 0)
     }
 
+    #[test]
+    fn art_paths() {
+        let skin = fixtures::skin(1001, "Goth Annie", None);
+        assert_eq!(
+            skin.art_path(ArtKind::Centered).to_str(),
+            Some("v1/champion-splashes/1/1001.jpg")
+        );
+        assert_eq!(
+            skin.art_path(ArtKind::Uncentered).to_str(),
+            Some("v1/champion-splashes/uncentered/1/1001.jpg")
+        );
+        let from_ddragon = Skin {
+            id: 1001,
+            ..Skin::default()
+        };
+        assert_eq!(
+            from_ddragon.art_path(ArtKind::Uncentered),
+            skin.art_path(ArtKind::Uncentered)
+        );
+        assert_eq!(
+            from_ddragon.art_path(ArtKind::Centered),
+            skin.art_path(ArtKind::Centered)
+        );
+    }
+
+    #[test]
+    fn search_skins() {
+        let cdragon = fixtures::cdragon();
+        let ids = |query: &SkinQuery| -> Vec<u64> {
+            cdragon
+                .search(query)
+                .unwrap()
+                .iter()
+                .map(|skin| skin.id)
+                .collect()
+        };
+        let star_guardian = SkinQuery {
+            skin_line: Some(String::from("star guardian")),
+            ..SkinQuery::default()
+        };
+        assert_eq!(ids(&star_guardian), vec![103015, 222020]);
+        assert_eq!(
+            ids(&SkinQuery {
+                champion: Some(String::from("jinx")),
+                ..star_guardian.clone()
+            }),
+            vec![222020]
+        );
+        assert_eq!(
+            ids(&SkinQuery {
+                rarity: Some(String::from("epic")),
+                name: Some(String::from("ahri")),
+                ..SkinQuery::default()
+            }),
+            vec![103015]
+        );
+        assert!(cdragon.search(&SkinQuery::default()).is_err());
+    }
+
+    #[test]
+    fn patches_and_new_skins() {
+        let listing = r#"[
+            {"name": "13.24", "type": "directory"},
+            {"name": "14.10", "type": "directory"},
+            {"name": "14.9", "type": "directory"},
+            {"name": "latest", "type": "directory"},
+            {"name": "pbe", "type": "directory"}
+        ]"#;
+        assert_eq!(parse_patches(listing).unwrap(), ["14.10", "14.9", "13.24"]);
+
+        let newer = fixtures::cdragon();
+        let mut older = fixtures::cdragon();
+        older.patch = String::from("14.9");
+        older.champions.remove(&222);
+        older.champions.get_mut(&103).unwrap().skins.remove(&103015);
+        let new: Vec<u64> = newer.new_skins(&older).iter().map(|skin| skin.id).collect();
+        assert_eq!(new, [103015, 222000, 222020]);
+
+        assert!(older.cache().unwrap().ends_with("cdragon-14.9.json"));
+        assert!(newer.cache().unwrap().ends_with("cdragon.json"));
+    }
+
+    #[test]
+    fn remote_changes() {
+        let remote = |etag: Option<&str>, modified: Option<&str>, size: Option<u64>| RemoteSplash {
+            url: String::from("https://example.com/1000.jpg"),
+            etag: etag.map(String::from),
+            last_modified: modified.map(String::from),
+            size,
+        };
+        let stored = remote(Some("\"a\""), Some("Mon, 01 Jan 2024"), Some(100));
+        assert!(!stored.changed(&stored));
+        assert!(stored.changed(&remote(Some("\"b\""), Some("Mon, 01 Jan 2024"), Some(100))));
+        // the etag wins over everything else
+        assert!(!stored.changed(&remote(Some("\"a\""), Some("Tue, 02 Jan 2024"), Some(200))));
+        assert!(stored.changed(&remote(None, Some("Tue, 02 Jan 2024"), Some(100))));
+        assert!(stored.changed(&remote(None, None, Some(200))));
+        assert!(!stored.changed(&remote(None, None, None)));
+    }
+
     #[test]
     fn out_of_date() {
         let date = Utc.with_ymd_and_hms(2023, 12, 31, 12, 0, 0).unwrap();
@@ -0,0 +1,245 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cache::Cached,
+    cdragon::{Champion, Skin},
+};
+
+/// Every change seen when the skins were refreshed, oldest first
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Changelog {
+    pub entries: Vec<ChangelogEntry>,
+}
+
+impl Cached for Changelog {
+    fn cache_name() -> String {
+        String::from("changelog")
+    }
+}
+
+/// What changed between two fetches of the skins
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChangelogEntry {
+    pub date: DateTime<Utc>,
+    /// The source's versions before and after, see [`crate::cdragon::SplashSource::version`]
+    pub from_version: String,
+    pub to_version: String,
+    pub new_champions: Vec<Named>,
+    /// Skins that weren't there before, new champions' skins included
+    pub new_skins: Vec<Named>,
+    pub removed_skins: Vec<Named>,
+    pub renamed_skins: Vec<Renamed>,
+    /// Skins whose splash art moved, downloaded copies of them may be out of date
+    pub updated_splashes: Vec<Named>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Named {
+    pub id: u64,
+    pub name: String,
+}
+
+impl Named {
+    fn skin(skin: &Skin) -> Self {
+        Self {
+            id: skin.id,
+            name: skin.name.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Renamed {
+    pub id: u64,
+    pub from: String,
+    pub to: String,
+}
+
+impl ChangelogEntry {
+    /// Compare the champions from before and after a refresh, everything is ordered by id
+    pub fn between(old: &HashMap<u64, Champion>, new: &HashMap<u64, Champion>) -> Self {
+        let old_skins: HashMap<u64, &Skin> = skins(old).map(|skin| (skin.id, skin)).collect();
+        let new_skins: HashMap<u64, &Skin> = skins(new).map(|skin| (skin.id, skin)).collect();
+        let mut entry = Self {
+            date: Utc::now(),
+            ..Self::default()
+        };
+        for champion in new
+            .values()
+            .filter(|champion| !old.contains_key(&champion.id))
+        {
+            entry.new_champions.push(Named {
+                id: champion.id,
+                name: champion.name.clone(),
+            });
+        }
+        for (id, skin) in &new_skins {
+            match old_skins.get(id) {
+                None => entry.new_skins.push(Named::skin(skin)),
+                Some(old) => {
+                    if old.name != skin.name {
+                        entry.renamed_skins.push(Renamed {
+                            id: *id,
+                            from: old.name.clone(),
+                            to: skin.name.clone(),
+                        });
+                    }
+                    if old.splash_path != skin.splash_path
+                        || old.uncentered_splash_path != skin.uncentered_splash_path
+                    {
+                        entry.updated_splashes.push(Named::skin(skin));
+                    }
+                }
+            }
+        }
+        for (id, skin) in &old_skins {
+            if !new_skins.contains_key(id) {
+                entry.removed_skins.push(Named::skin(skin));
+            }
+        }
+        entry.new_champions.sort_by_key(|champion| champion.id);
+        entry.new_skins.sort_by_key(|skin| skin.id);
+        entry.removed_skins.sort_by_key(|skin| skin.id);
+        entry.renamed_skins.sort_by_key(|skin| skin.id);
+        entry.updated_splashes.sort_by_key(|skin| skin.id);
+        entry
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.new_champions.is_empty()
+            && self.new_skins.is_empty()
+            && self.removed_skins.is_empty()
+            && self.renamed_skins.is_empty()
+            && self.updated_splashes.is_empty()
+    }
+
+    /// A one line count of the changes, like `3 new skins, 1 renamed`
+    pub fn summary(&self) -> String {
+        let counts = [
+            (self.new_champions.len(), "new champions"),
+            (self.new_skins.len(), "new skins"),
+            (self.removed_skins.len(), "removed"),
+            (self.renamed_skins.len(), "renamed"),
+            (self.updated_splashes.len(), "updated splashes"),
+        ];
+        let parts: Vec<String> = counts
+            .iter()
+            .filter(|(count, _)| *count > 0)
+            .map(|(count, label)| format!("{count} {label}"))
+            .collect();
+        match parts.is_empty() {
+            true => String::from("no changes"),
+            false => parts.join(", "),
+        }
+    }
+}
+
+fn skins(champions: &HashMap<u64, Champion>) -> impl Iterator<Item = &Skin> {
+    champions
+        .values()
+        .flat_map(|champion| champion.skins.values())
+}
+
+impl Changelog {
+    /// The saved history, empty if nothing has changed yet
+    pub fn read() -> anyhow::Result<Self> {
+        let mut changelog = Self::default();
+        if changelog.cache()?.exists() {
+            changelog.load()?;
+        }
+        Ok(changelog)
+    }
+
+    /// Add `entry` to the saved history
+    pub fn record(entry: ChangelogEntry) -> anyhow::Result<()> {
+        Self::default().transaction(|changelog| {
+            changelog.entries.push(entry);
+            Ok(())
+        })
+    }
+
+    /// The last `count` entries, newest first
+    pub fn latest(&self, count: usize) -> Vec<&ChangelogEntry> {
+        self.entries.iter().rev().take(count).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use crate::cdragon::fixtures;
+
+    use super::{Changelog, ChangelogEntry, Named, Renamed};
+
+    #[test]
+    fn diff_champions() {
+        let old = fixtures::cdragon().champions;
+        let mut new = fixtures::cdragon().champions;
+        new.remove(&222);
+        let ahri = new.get_mut(&103).unwrap();
+        ahri.skins.insert(
+            103016,
+            fixtures::skin(103016, "Arcade Ahri", Some("Arcade")),
+        );
+        ahri.skins.get_mut(&103015).unwrap().name = String::from("Star Guardian Ahri 2");
+        let annie = new.get_mut(&1).unwrap();
+        annie.skins.get_mut(&1001).unwrap().splash_path = String::from("moved.jpg");
+        new.extend([fixtures::champion(
+            893,
+            "Aurora",
+            vec![fixtures::skin(893000, "Aurora", None)],
+        )]);
+
+        let entry = ChangelogEntry::between(&old, &new);
+        let named = |id: u64, name: &str| Named {
+            id,
+            name: name.to_string(),
+        };
+        assert_eq!(entry.new_champions, [named(893, "Aurora")]);
+        assert_eq!(
+            entry.new_skins,
+            [named(103016, "Arcade Ahri"), named(893000, "Aurora")]
+        );
+        assert_eq!(
+            entry.removed_skins,
+            [named(222000, "Jinx"), named(222020, "Star Guardian Jinx")]
+        );
+        assert_eq!(
+            entry.renamed_skins,
+            [Renamed {
+                id: 103015,
+                from: String::from("Star Guardian Ahri"),
+                to: String::from("Star Guardian Ahri 2"),
+            }]
+        );
+        assert_eq!(entry.updated_splashes, [named(1001, "Goth Annie")]);
+        assert_eq!(
+            entry.summary(),
+            "1 new champions, 2 new skins, 2 removed, 1 renamed, 1 updated splashes"
+        );
+        assert!(ChangelogEntry::between(&old, &old).is_empty());
+    }
+
+    #[test]
+    fn record_history() {
+        assert!(Changelog::read().unwrap().entries.is_empty());
+        for version in ["a", "b", "c"] {
+            Changelog::record(ChangelogEntry {
+                to_version: version.to_string(),
+                ..ChangelogEntry::between(&HashMap::new(), &fixtures::cdragon().champions)
+            })
+            .unwrap();
+        }
+        let changelog = Changelog::read().unwrap();
+        let latest: Vec<&str> = changelog
+            .latest(2)
+            .iter()
+            .map(|entry| entry.to_version.as_str())
+            .collect();
+        assert_eq!(latest, ["c", "b"]);
+    }
+}
@@ -0,0 +1,453 @@
+use std::{fs, path::PathBuf};
+
+use anyhow::{anyhow, Context};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use toml::{Table, Value};
+
+use crate::{
+    app::TileLayout,
+    cache::write_atomic,
+    cdragon::{ArtKind, SourceKind, LATEST_PATCH},
+    output::OutputFormat,
+    palette::Arrangement,
+    paths,
+    resize::{ResizeBackend, ResizeFilter, ResizeOptions},
+    tiled_splash::{BuildOptions, MB},
+    wallpaper::BackendKind,
+};
+
+/// Every key `config get` and `config set` accept, with what it sets
+pub const KEYS: &[(&str, &str)] = &[
+    (
+        "art.kind",
+        "which splash art is downloaded, uncentered or centered",
+    ),
+    (
+        "art.locale",
+        "the CommunityDragon locale skin names are fetched in, like en_gb",
+    ),
+    (
+        "source.primary",
+        "where skins and splashes are fetched from, community-dragon or data-dragon",
+    ),
+    (
+        "source.fallback",
+        "the source tried when the primary one fails, the same as the primary to never fall back",
+    ),
+    (
+        "source.patch",
+        "the game patch skins and splashes come from, like 14.8, or latest",
+    ),
+    (
+        "tile.arrangement",
+        "how new tiles and rotations order their splashes",
+    ),
+    (
+        "tile.min-cell",
+        "the smallest the cells of new tiles may be shrunk to, as WIDTHxHEIGHT",
+    ),
+    ("tile.quality", "the JPEG quality of built tiles, 1 to 100"),
+    (
+        "tile.memory-limit",
+        "roughly how many MB building a tile may use, bigger tiles are saved as PNGs",
+    ),
+    (
+        "tile.filter",
+        "how cells are resized: nearest, triangle, catmull-rom, gaussian or lanczos3",
+    ),
+    (
+        "tile.fast-decode",
+        "whether JPEGs are shrunk while they're decoded, faster but slightly softer, when unset only splashes at least twice the cell size are",
+    ),
+    (
+        "tile.resize-backend",
+        "what resizes cells: image, or simd to filter on SIMD lanes",
+    ),
+    (
+        "output.format",
+        "how results are printed, plain, table or json",
+    ),
+    (
+        "download.concurrency",
+        "how many splashes are downloaded at once, 0 for one per core",
+    ),
+    (
+        "download.offline",
+        "auto to fall back to cached skins, online to always check for new ones, offline to never",
+    ),
+    (
+        "wallpaper.backend",
+        "the program that sets wallpapers, detected from the desktop when unset",
+    ),
+];
+
+/// Settings read from `config.toml` in the config directory
+///
+/// Every setting is a default, applied from highest priority first:
+/// 1. a flag on the command line
+/// 2. a setting saved on the tile, like the arrangement and min cell `tile build` remembers
+/// 3. `config.toml`
+/// 4. the defaults here
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub art: ArtConfig,
+    pub source: SourceConfig,
+    pub tile: TileConfig,
+    pub output: OutputConfig,
+    pub download: DownloadConfig,
+    pub wallpaper: WallpaperConfig,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct ArtConfig {
+    /// Only applies to splashes downloaded from then on
+    pub kind: ArtKind,
+    pub locale: String,
+}
+
+impl Default for ArtConfig {
+    fn default() -> Self {
+        Self {
+            kind: ArtKind::default(),
+            locale: String::from("default"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct SourceConfig {
+    pub primary: SourceKind,
+    pub fallback: SourceKind,
+    pub patch: String,
+}
+
+impl Default for SourceConfig {
+    fn default() -> Self {
+        Self {
+            primary: SourceKind::CommunityDragon,
+            fallback: SourceKind::DataDragon,
+            patch: LATEST_PATCH.to_string(),
+        }
+    }
+}
+
+impl SourceConfig {
+    /// The sources to try, in order
+    pub fn order(&self) -> Vec<SourceKind> {
+        match self.primary == self.fallback {
+            true => vec![self.primary],
+            false => vec![self.primary, self.fallback],
+        }
+    }
+}
+
+/// The most tile.memory-limit can be, in MB, a terabyte is already more than any build needs
+const MAX_MEMORY_LIMIT: u64 = 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct TileConfig {
+    pub arrangement: Arrangement,
+    #[serde(with = "dims")]
+    pub min_cell: (u32, u32),
+    pub quality: u8,
+    /// In MB
+    pub memory_limit: u64,
+    pub filter: ResizeFilter,
+    /// Decided per splash when unset
+    pub fast_decode: Option<bool>,
+    pub resize_backend: ResizeBackend,
+}
+
+impl Default for TileConfig {
+    fn default() -> Self {
+        Self {
+            arrangement: Arrangement::default(),
+            min_cell: (0, 0),
+            quality: 75,
+            memory_limit: 1024,
+            filter: ResizeFilter::default(),
+            fast_decode: None,
+            resize_backend: ResizeBackend::default(),
+        }
+    }
+}
+
+impl TileConfig {
+    /// The layout new tiles start with
+    pub fn layout(&self) -> TileLayout {
+        TileLayout {
+            min_cell: self.min_cell,
+        }
+    }
+
+    pub fn build_options(&self) -> BuildOptions {
+        BuildOptions {
+            quality: self.quality,
+            memory_limit: self.memory_limit.saturating_mul(MB),
+            resize: ResizeOptions {
+                filter: self.filter,
+                fast_decode: self.fast_decode,
+                backend: self.resize_backend,
+            },
+            ..BuildOptions::default()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct OutputConfig {
+    pub format: OutputFormat,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct DownloadConfig {
+    pub concurrency: usize,
+    pub offline: OfflinePolicy,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct WallpaperConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backend: Option<BackendKind>,
+}
+
+/// When skin data is fetched
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum OfflinePolicy {
+    /// Check for new skins, using the cached ones if no source can be reached
+    #[default]
+    Auto,
+    /// Check for new skins and fail if no source can be reached
+    Online,
+    /// Only use the cached skins
+    Offline,
+}
+
+impl Config {
+    pub fn path() -> anyhow::Result<PathBuf> {
+        Ok(paths::get()?.config.join("config.toml"))
+    }
+
+    /// Load `config.toml`, or the defaults if there isn't one
+    pub fn load() -> anyhow::Result<Self> {
+        Self::from_table(read_table()?)
+            .with_context(|| format!("invalid config in {:?}", Self::path().unwrap_or_default()))
+    }
+
+    fn from_table(table: Table) -> anyhow::Result<Self> {
+        let config: Config = Value::Table(table).try_into()?;
+        if !(1..=100).contains(&config.tile.quality) {
+            return Err(anyhow!(
+                "tile.quality has to be between 1 and 100, not {}",
+                config.tile.quality
+            ));
+        }
+        if !(1..=MAX_MEMORY_LIMIT).contains(&config.tile.memory_limit) {
+            return Err(anyhow!(
+                "tile.memory-limit has to be between 1 and {MAX_MEMORY_LIMIT}, not {}",
+                config.tile.memory_limit
+            ));
+        }
+        Ok(config)
+    }
+
+    /// The value of `key`, or `None` when it isn't set and has no default
+    pub fn get(&self, key: &str) -> anyhow::Result<Option<String>> {
+        check_key(key)?;
+        let value = Value::try_from(self)?;
+        Ok(key
+            .split('.')
+            .try_fold(&value, |value, part| value.get(part))
+            .map(display))
+    }
+
+    /// Every key with its value, unset keys included
+    pub fn list(&self) -> anyhow::Result<Vec<(&'static str, Option<String>)>> {
+        KEYS.iter()
+            .map(|(key, _)| Ok((*key, self.get(key)?)))
+            .collect()
+    }
+
+    /// Save `value` for `key` in `config.toml`, keeping everything else in the file as it was
+    ///
+    /// The value is read as TOML when it can be and as a string otherwise, so `90` is a number
+    /// and `json` is a string. Values that only fit as a string, like the patch `14.8`, are
+    /// saved as one.
+    pub fn set(key: &str, value: &str) -> anyhow::Result<Self> {
+        check_key(key)?;
+        let (section, name) = key.split_once('.').unwrap();
+        let table = read_table()?;
+        let with = |parsed: Value| -> anyhow::Result<(Table, Self)> {
+            let mut table = table.clone();
+            table
+                .entry(section)
+                .or_insert_with(|| Value::Table(Table::new()))
+                .as_table_mut()
+                .ok_or(anyhow!("{section} in the config file isn't a table"))?
+                .insert(name.to_string(), parsed);
+            let config = Self::from_table(table.clone())?;
+            Ok((table, config))
+        };
+        let (updated, config) = match parse_value(value) {
+            parsed @ Value::String(_) => with(parsed),
+            parsed => with(parsed).or_else(|_| with(Value::String(value.to_string()))),
+        }
+        .with_context(|| format!("{value} isn't a valid value for {key}"))?;
+        write_table(&updated)?;
+        Ok(config)
+    }
+
+    /// Remove `key` from `config.toml` so it goes back to its default
+    pub fn unset(key: &str) -> anyhow::Result<Self> {
+        check_key(key)?;
+        let (section, name) = key.split_once('.').unwrap();
+        let mut table = read_table()?;
+        if let Some(section) = table.get_mut(section).and_then(Value::as_table_mut) {
+            section.remove(name);
+        }
+        let config = Self::from_table(table.clone())?;
+        write_table(&table)?;
+        Ok(config)
+    }
+}
+
+fn check_key(key: &str) -> anyhow::Result<()> {
+    match KEYS.iter().any(|(known, _)| *known == key) {
+        true => Ok(()),
+        false => Err(anyhow!(
+            "unknown config key {key}, run `config list` to see them all"
+        )),
+    }
+}
+
+fn read_table() -> anyhow::Result<Table> {
+    let path = Config::path()?;
+    if !path.exists() {
+        return Ok(Table::new());
+    }
+    let contents =
+        fs::read_to_string(&path).with_context(|| format!("failed to read {:?}", path))?;
+    contents
+        .parse()
+        .with_context(|| format!("{:?} isn't valid TOML", path))
+}
+
+fn write_table(table: &Table) -> anyhow::Result<()> {
+    paths::get()?.ensure(paths::Location::Config)?;
+    write_atomic(&Config::path()?, &toml::to_string_pretty(table)?)
+}
+
+fn parse_value(value: &str) -> Value {
+    format!("value = {value}")
+        .parse::<Table>()
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| Value::String(value.to_string()))
+}
+
+fn display(value: &Value) -> String {
+    match value {
+        Value::String(string) => string.clone(),
+        value => value.to_string(),
+    }
+}
+
+/// Parse dimensions written as `WIDTHxHEIGHT`, like `640x360`
+pub fn parse_dims(dims: &str) -> anyhow::Result<(u32, u32)> {
+    let (width, height) = dims
+        .split_once('x')
+        .ok_or(anyhow!("expected WIDTHxHEIGHT, like 640x360"))?;
+    Ok((width.trim().parse()?, height.trim().parse()?))
+}
+
+/// Dimensions kept as `WIDTHxHEIGHT` strings so they read the same as on the command line
+mod dims {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(dims: &(u32, u32), serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format!("{}x{}", dims.0, dims.1))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<(u32, u32), D::Error> {
+        let dims = String::deserialize(deserializer)?;
+        super::parse_dims(&dims).map_err(D::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use crate::{
+        cdragon::SourceKind, output::OutputFormat, palette::Arrangement, resize::ResizeFilter,
+    };
+
+    use super::{Config, OfflinePolicy, KEYS};
+
+    #[test]
+    fn set_and_unset() {
+        assert_eq!(Config::load().unwrap(), Config::default());
+        let config = Config::set("tile.min-cell", "640x360").unwrap();
+        assert_eq!(config.tile.min_cell, (640, 360));
+        Config::set("tile.quality", "90").unwrap();
+        Config::set("output.format", "json").unwrap();
+
+        let config = Config::load().unwrap();
+        assert_eq!(config.tile.quality, 90);
+        assert_eq!(config.output.format, OutputFormat::Json);
+        assert_eq!(config.tile.arrangement, Arrangement::None);
+        assert_eq!(config.download.offline, OfflinePolicy::Auto);
+        assert_eq!(
+            config.get("tile.min-cell").unwrap().as_deref(),
+            Some("640x360")
+        );
+
+        assert!(Config::set("tile.quality", "101").is_err());
+        assert!(Config::set("tile.memory-limit", "0").is_err());
+        assert!(Config::set("tile.memory-limit", &u64::MAX.to_string()).is_err());
+        let config = Config::set("tile.memory-limit", "64").unwrap();
+        assert_eq!(config.tile.build_options().memory_limit, 64 * 1024 * 1024);
+        let config = Config::set("tile.filter", "catmull-rom").unwrap();
+        assert_eq!(config.tile.filter, ResizeFilter::CatmullRom);
+        assert!(Config::set("tile.filter", "bicubic").is_err());
+        assert!(Config::set("tile.arrangement", "sideways").is_err());
+        assert!(Config::set("tile.colour", "red").is_err());
+        assert_eq!(Config::load().unwrap().tile.quality, 90);
+
+        assert_eq!(Config::unset("tile.quality").unwrap().tile.quality, 75);
+
+        assert_eq!(config.source.order().len(), 2);
+        let config = Config::set("source.fallback", "community-dragon").unwrap();
+        assert_eq!(config.source.order(), vec![SourceKind::CommunityDragon]);
+        assert_eq!(
+            Config::set("source.patch", "14.8").unwrap().source.patch,
+            "14.8"
+        );
+    }
+
+    #[test]
+    fn bad_values_can_be_unset() {
+        fs::create_dir_all(Config::path().unwrap().parent().unwrap()).unwrap();
+        fs::write(Config::path().unwrap(), "[tile]\nquality = 0\n").unwrap();
+        assert!(Config::load().is_err());
+        assert_eq!(Config::unset("tile.quality").unwrap().tile.quality, 75);
+        assert!(Config::load().is_ok());
+    }
+
+    #[test]
+    fn list_every_key() {
+        let listed = Config::default().list().unwrap();
+        assert_eq!(listed.len(), KEYS.len());
+        assert!(listed.contains(&("wallpaper.backend", None)));
+        assert!(listed.contains(&("art.locale", Some(String::from("default")))));
+    }
+}
@@ -1,61 +1,224 @@
-use std::{io::Error, sync::mpsc, thread};
+use std::{collections::HashMap, sync::OnceLock};
 
-use crate::splashes::Skin;
+use anyhow::{anyhow, Context};
+use serde::Deserialize;
 
-pub fn get_latest_version() -> String {
-    let (tx, rx) = mpsc::channel();
-    thread::spawn(move || {
-        let v = fetch_latest_version();
-        let _ = tx.send(v);
-    });
-    rx.recv().expect("Problem getting latest version").unwrap()
+use crate::cdragon::{fetch, ArtKind, Champion, Skin, SplashSource, DEFAULT_LOCALE, LATEST_PATCH};
+
+const DDRAGON_URL: &str = "https://ddragon.leagueoflegends.com";
+
+/// Riot's Data Dragon, a fallback for when CommunityDragon is slow or down
+///
+/// It only has names for skins, so skin line and rarity searches find nothing in its skins.
+/// Splash art isn't kept per patch, so it's always the latest.
+#[derive(Debug)]
+pub struct DataDragon {
+    /// The patch skins are fetched for, like `14.8`, or [`LATEST_PATCH`]
+    patch: String,
+    /// The full version of the patch, looked up once
+    version: OnceLock<String>,
+}
+
+impl Default for DataDragon {
+    fn default() -> Self {
+        Self::pinned(LATEST_PATCH)
+    }
+}
+
+impl DataDragon {
+    pub fn pinned(patch: &str) -> Self {
+        Self {
+            patch: patch.to_string(),
+            version: OnceLock::new(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ChampionFull {
+    data: HashMap<String, ChampionData>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChampionData {
+    /// The champion's alias, like `MonkeyKing`
+    id: String,
+    /// The champion's numeric id as a string
+    key: String,
+    name: String,
+    skins: Vec<SkinData>,
 }
 
-pub fn splash_url(skin: &Skin) -> String {
+#[derive(Debug, Deserialize)]
+struct SkinData {
+    id: String,
+    num: u64,
+    name: String,
+}
+
+impl SplashSource for DataDragon {
+    /// The newest release of the patch, like `14.8.1`
+    fn version(&self) -> anyhow::Result<String> {
+        if let Some(version) = self.version.get() {
+            return Ok(version.clone());
+        }
+        let version = pick_version(fetch_versions()?, &self.patch)?;
+        Ok(self.version.get_or_init(|| version).clone())
+    }
+
+    fn champions(&self, locale: &str) -> anyhow::Result<HashMap<u64, Champion>> {
+        let url = format!(
+            "{DDRAGON_URL}/cdn/{}/data/{}/championFull.json",
+            self.version()?,
+            ddragon_locale(locale)
+        );
+        let champions = fetch(url)
+            .and_then(|response| response.error_for_status())
+            .and_then(|response| response.text())
+            .with_context(|| "error fetching champions")?;
+        parse_champions(&champions)
+    }
+
+    fn art_url(&self, champion: &Champion, skin: &Skin, kind: ArtKind) -> String {
+        splash_url(&champion.alias, skin, kind)
+    }
+}
+
+/// Every version, newest first
+fn fetch_versions() -> anyhow::Result<Vec<String>> {
+    let versions = fetch(format!("{DDRAGON_URL}/api/versions.json"))
+        .and_then(|response| response.error_for_status())
+        .and_then(|response| response.text())
+        .with_context(|| "error fetching versions")?;
+    serde_json::from_str(&versions).with_context(|| "failed to deserialize versions")
+}
+
+/// The newest version that's part of `patch`, so `14.8` picks `14.8.1`
+fn pick_version(versions: Vec<String>, patch: &str) -> anyhow::Result<String> {
+    versions
+        .into_iter()
+        .find(|version| {
+            patch == LATEST_PATCH || version == patch || version.starts_with(&format!("{patch}."))
+        })
+        .ok_or(anyhow!("Data Dragon doesn't have patch {patch}"))
+}
+
+/// Data Dragon spells locales with an uppercase region, like `en_GB` where CommunityDragon has
+/// `en_gb`
+fn ddragon_locale(locale: &str) -> String {
+    match locale.split_once('_') {
+        _ if locale == DEFAULT_LOCALE => String::from("en_US"),
+        Some((language, region)) => format!("{language}_{}", region.to_uppercase()),
+        None => locale.to_string(),
+    }
+}
+
+/// Turn `championFull.json` into champions keyed by id
+fn parse_champions(json: &str) -> anyhow::Result<HashMap<u64, Champion>> {
+    let full: ChampionFull =
+        serde_json::from_str(json).with_context(|| "failed to deserialize champions")?;
+    full.data
+        .into_values()
+        .map(|data| {
+            let id: u64 = data
+                .key
+                .parse()
+                .with_context(|| format!("invalid id for {}", data.name))?;
+            let skins = data
+                .skins
+                .into_iter()
+                .map(|skin| {
+                    let id: u64 = skin
+                        .id
+                        .parse()
+                        .with_context(|| format!("invalid id for skin {}", skin.name))?;
+                    let skin = Skin {
+                        id,
+                        // base skins are just called default
+                        name: match skin.num {
+                            0 => data.name.clone(),
+                            _ => skin.name,
+                        },
+                        is_base: skin.num == 0,
+                        ..Skin::default()
+                    };
+                    Ok((id, skin))
+                })
+                .collect::<anyhow::Result<_>>()?;
+            Ok((
+                id,
+                Champion {
+                    id,
+                    name: data.name,
+                    alias: data.id,
+                    skins,
+                },
+            ))
+        })
+        .collect()
+}
+
+fn splash_url(alias: &str, skin: &Skin, kind: ArtKind) -> String {
+    let folder = match kind {
+        ArtKind::Uncentered => "splash",
+        ArtKind::Centered => "centered",
+    };
     format!(
-        "https://ddragon.leagueoflegends.com/cdn/img/champion/splash/{}_{}.jpg",
-        if skin.id == "9027" {
-            "FiddleSticks".to_string()
+        "{DDRAGON_URL}/cdn/img/champion/{folder}/{}_{}.jpg",
+        // Data Dragon keeps every Fiddlesticks splash under its `Fiddlesticks` alias except
+        // Star Nemesis Fiddlesticks, which is only there under the older `FiddleSticks` spelling
+        if skin.id == 9027 {
+            "FiddleSticks"
         } else {
-            skin.champ.clone()
+            alias
         },
-        skin.num
+        skin.id % 1000
     )
 }
 
-pub fn preview_splash(skin: &Skin) -> Result<(), Error> {
-    let url = splash_url(skin);
-    open::that(url)?;
-    Ok(())
-}
+#[cfg(test)]
+mod test {
+    use crate::cdragon::{fixtures, ArtKind};
 
-pub fn request_champs(patch: &str) -> Result<String, reqwest::Error> {
-    let res = reqwest::blocking::get(format!(
-        "https://ddragon.leagueoflegends.com/cdn/{}/data/en_US/champion.json",
-        patch
-    ))?;
+    use super::{ddragon_locale, parse_champions, pick_version, splash_url};
 
-    let result = res.text()?;
-    Ok(result)
-}
+    #[test]
+    fn parse_champion_full() {
+        let json = r#"{"type": "champion", "data": {"MonkeyKing": {
+            "id": "MonkeyKing", "key": "62", "name": "Wukong",
+            "skins": [
+                {"id": "62000", "num": 0, "name": "default", "chromas": false},
+                {"id": "62001", "num": 1, "name": "Volcanic Wukong", "chromas": false}
+            ]}}}"#;
+        let champions = parse_champions(json).unwrap();
+        let wukong = &champions[&62];
+        assert_eq!(
+            (wukong.name.as_str(), wukong.alias.as_str()),
+            ("Wukong", "MonkeyKing")
+        );
+        assert_eq!(wukong.skins[&62000].name, "Wukong");
+        assert!(wukong.skins[&62000].is_base);
+        assert_eq!(wukong.skins[&62001].name, "Volcanic Wukong");
+        assert!(parse_champions(r#"{"data": {"Annie": {"id": "Annie"}}}"#).is_err());
+    }
 
-pub fn fetch_latest_version() -> Option<String> {
-    let versions = fetch_versions().unwrap_or_else(|error| {
-        panic!("Couldn't fetch versions: {error}");
-    });
-    let versions: Vec<String> = serde_json::from_str(&versions).unwrap_or_else(|error| {
-        panic!("Invalid response: {error}");
-    });
-    let versions: Vec<String> = versions
-        .iter()
-        .map(|version| version.replace('\"', ""))
-        .collect();
-    versions.first().cloned()
-}
+    #[test]
+    fn urls_and_locales() {
+        let skin = fixtures::skin(1001, "Goth Annie", None);
+        assert_eq!(
+            splash_url("Annie", &skin, ArtKind::Centered),
+            "https://ddragon.leagueoflegends.com/cdn/img/champion/centered/Annie_1.jpg"
+        );
+        assert_eq!(
+            splash_url("Annie", &skin, ArtKind::Uncentered),
+            "https://ddragon.leagueoflegends.com/cdn/img/champion/splash/Annie_1.jpg"
+        );
+        assert_eq!(ddragon_locale("default"), "en_US");
+        assert_eq!(ddragon_locale("en_gb"), "en_GB");
 
-pub fn fetch_versions() -> Result<String, reqwest::Error> {
-    let url = String::from("https://ddragon.leagueoflegends.com/api/versions.json");
-    let res = reqwest::blocking::get(url)?;
-    let result = res.text()?;
-    Ok(result)
+        let versions = || ["14.9.1", "14.8.2", "14.8.1"].map(String::from).to_vec();
+        assert_eq!(pick_version(versions(), "latest").unwrap(), "14.9.1");
+        assert_eq!(pick_version(versions(), "14.8").unwrap(), "14.8.2");
+        assert!(pick_version(versions(), "14.1").is_err());
+    }
 }
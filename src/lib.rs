@@ -1,4 +1,19 @@
 pub mod app;
+pub mod build_cache;
 pub mod cache;
 pub mod cdragon;
+pub mod changelog;
+pub mod config;
+pub mod datadragon;
+pub mod output;
+pub mod palette;
+pub mod paths;
+pub mod preset;
+pub mod preview;
+pub mod resize;
+pub mod rotate;
+pub mod sidecar;
+pub mod stitch;
 pub mod tiled_splash;
+pub mod tui;
+pub mod wallpaper;
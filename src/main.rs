@@ -1,17 +1,56 @@
-use core::panic;
+use std::{
+    collections::HashSet,
+    fs,
+    io::{self, IsTerminal},
+    path::PathBuf,
+};
 
-use anyhow::{anyhow, bail, Context, Ok};
+use anyhow::{anyhow, Context, Ok};
 use clap::{Parser, Subcommand};
-use dialoguer::{Input, MultiSelect};
+use dialoguer::{Confirm, Input, MultiSelect};
 use display_info::DisplayInfo;
-use rayon::iter::{IntoParallelIterator, ParallelIterator};
-use rusty_splash::{app::App, cache::Cached, cdragon::CDragon};
+use rusty_splash::{
+    app::{App, TileInstance, TileLayout},
+    build_cache::CellCache,
+    cdragon::{CDragon, SkinQuery, LATEST_PATCH},
+    changelog::{Changelog, ChangelogEntry},
+    config::{parse_dims, Config},
+    output::{
+        emit, BuildOutput, CacheClearResult, ConfigEntry, DownloadResult, ExportResult,
+        ImportResult, OutputFormat, PatchDiff, PatchList, PreviewResult, RefreshResult,
+        SkinSummary, SyncResult, TileDetails, TileSummary, WallpaperResult, WhatsNew,
+    },
+    palette::Arrangement,
+    paths::{self, Location},
+    preset::Preset,
+    preview::{self, contact_sheet, Protocol, Terminal, THUMBNAIL},
+    resize::ResizeFilter,
+    rotate::Rotation,
+    tui,
+    wallpaper::{BackendKind, Environment, Output},
+};
+use uuid::Uuid;
 
 #[derive(Parser, Debug)]
 #[command(author = "Corey Stock", about)]
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+    /// Answer yes to every confirmation
+    #[arg(short, long, global = true)]
+    yes: bool,
+    /// How results are printed, defaults to output.format in the config
+    #[arg(short, long, global = true, value_enum)]
+    output: Option<OutputFormat>,
+    /// Keep app state, downloads, tiles and caches under this directory
+    ///
+    /// Overrides RUSTY_SPLASH_DATA_DIR and the XDG directories.
+    #[arg(long, global = true, value_name = "DIR")]
+    data_dir: Option<PathBuf>,
+    /// The game patch skins and splashes come from, like 14.8, defaults to source.patch in the
+    /// config
+    #[arg(long, global = true)]
+    patch: Option<String>,
 }
 
 #[derive(Subcommand, Debug)]
@@ -20,96 +59,936 @@ enum Commands {
     Tile(TileCommand),
     #[command()]
     Download { query: String },
+    /// Re-download splashes whose art changed since they were downloaded, keeping the old ones
+    /// in the archive
+    #[command()]
+    Sync {
+        /// Only report which splashes changed
+        #[arg(long)]
+        dry_run: bool,
+    },
+    #[command(subcommand)]
+    Wallpaper(WallpaperCommand),
+    /// Show what changed in the skins the last times they were refreshed
+    #[command()]
+    WhatsNew {
+        /// How many refreshes to show
+        #[arg(long, default_value_t = 1)]
+        last: usize,
+        /// Make a tile of the new skins with this name and select it
+        #[arg(long)]
+        tile: Option<String>,
+    },
+    /// List game patches and find the skins each one added
+    #[command(subcommand)]
+    Patch(PatchCommand),
+    /// Browse champions and skins full screen, adding them to a tile and building it
+    #[command()]
+    Browse {
+        /// The tile to curate, by name or id, defaults to the selected tile
+        #[arg(long)]
+        tile: Option<String>,
+    },
+    /// Show a built tile, a downloaded splash, or a contact sheet of several skins in the
+    /// terminal
+    #[command()]
+    Preview {
+        /// Skins to preview, several make a contact sheet
+        #[arg(long, value_delimiter = ',', conflicts_with_all = ["query", "tile"])]
+        ids: Vec<u64>,
+        /// Preview a contact sheet of every skin whose name contains this
+        #[arg(long, conflicts_with = "tile")]
+        query: Option<String>,
+        /// The built tile to preview, by name or id, defaults to the selected tile
+        #[arg(long)]
+        tile: Option<String>,
+        /// How to draw in the terminal, detected by default
+        #[arg(long, value_enum)]
+        protocol: Option<Protocol>,
+        /// Open the image in the default viewer instead of the terminal
+        #[arg(long)]
+        open: bool,
+        /// How many columns wide to draw, defaults to the terminal's width
+        #[arg(long)]
+        width: Option<u32>,
+    },
+    /// Read and change the defaults in config.toml
+    ///
+    /// Flags on the command line win over settings saved on a tile, which win over the config,
+    /// which wins over the built-in defaults.
+    #[command(subcommand)]
+    Config(ConfigCommand),
+    /// Manage files kept to speed things up
+    #[command(subcommand)]
+    Cache(CacheCommand),
+    /// Generate a series of distinct wallpapers for a desktop slideshow
+    #[command()]
+    Rotate {
+        /// How many wallpapers to generate
+        #[arg(short, long, default_value_t = 10)]
+        count: usize,
+        /// The tile to rotate through, by name or id, defaults to the selected tile
+        #[arg(long, conflicts_with = "query")]
+        tile: Option<String>,
+        /// Rotate through every skin matching a query instead of a tile
+        #[arg(long)]
+        query: Option<String>,
+        /// How many splashes each wallpaper uses, defaults to all of them
+        #[arg(long)]
+        per_wallpaper: Option<usize>,
+        /// How to order the splashes, defaults to tile.arrangement in the config
+        #[arg(long, value_enum)]
+        arrange: Option<Arrangement>,
+        /// How to resize the cells, defaults to tile.filter in the config
+        #[arg(long, value_enum)]
+        filter: Option<ResizeFilter>,
+        /// Seed for choosing subsets, so a rotation can be regenerated
+        #[arg(long)]
+        seed: Option<u64>,
+        /// Where to write the wallpapers and manifest
+        #[arg(long)]
+        out: Option<PathBuf>,
+    },
 }
 
 #[derive(Debug, Subcommand)]
 enum TileCommand {
+    /// Add skins to a tile, picking them interactively unless --ids or --all is given
     #[command()]
-    Add { query: String },
+    Add {
+        /// Only skins whose name contains this are offered
+        #[arg(required_unless_present = "ids")]
+        query: Option<String>,
+        /// Skin ids to add without prompting
+        #[arg(long, value_delimiter = ',')]
+        ids: Vec<u64>,
+        /// Add every skin matching the query without prompting
+        #[arg(long, requires = "query", conflicts_with = "ids")]
+        all: bool,
+        /// The tile to add to, by name or id, defaults to the selected tile
+        #[arg(long)]
+        tile: Option<String>,
+    },
+    /// Delete tiles, picking them interactively unless --tile or --all is given
     #[command()]
-    Delete,
+    Delete {
+        /// A tile to delete, by name or id
+        #[arg(long = "tile")]
+        tiles: Vec<String>,
+        /// Delete every tile
+        #[arg(long, conflicts_with = "tiles")]
+        all: bool,
+    },
     #[command()]
-    Build,
+    Build {
+        /// The tile to build, by name or id, defaults to the selected tile
+        #[arg(long)]
+        tile: Option<String>,
+        /// How to order the splashes, remembered for the next build of the tile
+        #[arg(long, value_enum)]
+        arrange: Option<Arrangement>,
+        /// The smallest a cell may be shrunk to, as WIDTHxHEIGHT, remembered for the next build
+        #[arg(long, value_parser = parse_dims)]
+        min_cell: Option<(u32, u32)>,
+        /// How to resize the cells for this build, defaults to tile.filter in the config
+        #[arg(long, value_enum)]
+        filter: Option<ResizeFilter>,
+    },
     #[command()]
     List,
+    /// Create a tile and select it, pass any query filter to make a smart tile whose skins are
+    /// re-evaluated every build
+    #[command()]
+    New {
+        name: Option<String>,
+        #[command(flatten)]
+        query: SkinQuery,
+    },
+    /// Re-run a smart tile's query and show what changed
+    #[command()]
+    Refresh {
+        /// The tile to refresh, by name or id, defaults to the selected tile
+        #[arg(long)]
+        tile: Option<String>,
+    },
+    /// Rename a tile
+    #[command()]
+    Rename {
+        /// The tile's current name or id
+        tile: String,
+        name: String,
+    },
+    /// Make a tile the one commands use by default
+    #[command()]
+    Select {
+        /// The tile's name or id
+        tile: String,
+    },
+    /// List the skins in a tile
+    #[command()]
+    Show {
+        /// The tile's name or id, defaults to the selected tile
+        tile: Option<String>,
+    },
+    /// Remove skins from a tile, picking them interactively unless --ids is given
+    #[command()]
+    Remove {
+        /// Skin ids to remove without prompting
+        #[arg(long, value_delimiter = ',')]
+        ids: Vec<u64>,
+        /// The tile to remove from, by name or id, defaults to the selected tile
+        #[arg(long)]
+        tile: Option<String>,
+    },
+    /// Remove every skin from a tile
     #[command()]
-    New { name: Option<String> },
+    Clear {
+        /// The tile to clear, by name or id, defaults to the selected tile
+        #[arg(long)]
+        tile: Option<String>,
+    },
+    /// Copy a tile's skins and settings into a new tile
+    #[command()]
+    Duplicate {
+        /// The tile's name or id
+        tile: String,
+        /// The new tile's name, defaults to "<name> copy"
+        name: Option<String>,
+    },
+    /// Write a tile to a preset file that can be shared, TOML if it ends in .toml, JSON otherwise
+    #[command()]
+    Export {
+        path: PathBuf,
+        /// The tile to export, by name or id, defaults to the selected tile
+        #[arg(long)]
+        tile: Option<String>,
+    },
+    /// Create a tile from a preset file
+    #[command()]
+    Import {
+        path: PathBuf,
+        /// Name the tile something other than the preset's name
+        #[arg(long)]
+        name: Option<String>,
+    },
 }
 
-fn main() -> anyhow::Result<()> {
-    let monitors = DisplayInfo::all()
-        .with_context(|| "failed to get display info")?
-        .iter()
-        .map(|monitor| (monitor.width, monitor.height))
-        .collect();
-    let mut app = App::new(monitors)?;
-    let mut cdragon = CDragon::new()?;
+#[derive(Debug, Subcommand)]
+enum WallpaperCommand {
+    /// Set a built tile as the wallpaper, one image per monitor
+    #[command()]
+    Set {
+        /// The tile to use, by name or id, defaults to the selected tile
+        #[arg(long, conflicts_with = "files")]
+        tile: Option<String>,
+        /// Which program sets the wallpaper, detected from the desktop by default
+        #[arg(long, value_enum)]
+        backend: Option<BackendKind>,
+        /// Print the commands instead of running them
+        #[arg(long)]
+        dry_run: bool,
+        /// Images to use instead of a tile, in monitor order
+        files: Vec<PathBuf>,
+    },
+}
 
+#[derive(Debug, Subcommand)]
+enum PatchCommand {
+    /// List the patches that can be pinned with --patch, newest first
+    #[command()]
+    List,
+    /// Show the skins a patch added, optionally making a tile of them
+    #[command()]
+    Diff {
+        /// The newer patch, like 14.8, or latest
+        to: String,
+        /// The patch to compare against, defaults to the one before
+        #[arg(long)]
+        from: Option<String>,
+        /// Make a tile of the new skins with this name and select it
+        #[arg(long)]
+        tile: Option<String>,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum ConfigCommand {
+    /// Print a setting
+    #[command()]
+    Get { key: String },
+    /// Save a setting
+    #[command()]
+    Set { key: String, value: String },
+    /// Remove a setting so it goes back to its default
+    #[command()]
+    Unset { key: String },
+    /// Print every setting and what it does
+    #[command()]
+    List,
+}
+
+#[derive(Debug, Subcommand)]
+enum CacheCommand {
+    /// Remove the resized splashes kept between builds
+    #[command()]
+    Clear,
+}
+
+fn main() -> anyhow::Result<()> {
     let cli = Cli::parse();
+    paths::init(cli.data_dir.clone())?;
+    if let Commands::Config(command) = cli.command {
+        // runs before the config is validated, so a bad value in it can still be fixed
+        let format = cli
+            .output
+            .or_else(|| Config::load().ok().map(|config| config.output.format))
+            .unwrap_or_default();
+        return config_command(command, format);
+    }
+    let mut config = Config::load()?;
+    if let Some(patch) = cli.patch {
+        config.source.patch = patch;
+    }
+    let format = cli.output.unwrap_or(config.output.format);
+    if let Commands::Cache(CacheCommand::Clear) = cli.command {
+        let (cells, bytes) = CellCache::open()?.prune(0)?;
+        return emit(format, "cache clear", &CacheClearResult { cells, bytes });
+    }
+    // displays are only asked for by the commands that build or set wallpapers, so the rest
+    // work without a desktop
+    let mut app = App::new(Vec::new())?;
+    let cdragon = CDragon::new(&config)?;
 
     match cli.command {
         Commands::Tile(tile) => match tile {
             TileCommand::List => {
-                let tile_names: Vec<String> =
-                    app.tiles.iter().map(|tile| tile.name.clone()).collect();
-                for name in tile_names {
-                    println!("{name}");
+                let tiles: Vec<TileSummary> = app
+                    .tiles
+                    .iter()
+                    .map(|tile| TileSummary::new(&app, tile))
+                    .collect();
+                emit(format, "tile list", &tiles)?;
+            }
+            TileCommand::Build {
+                tile,
+                arrange: arrangement,
+                min_cell,
+                filter,
+            } => {
+                let mut tile = app.tile_find(tile.as_deref())?.clone();
+                if let Some(arrangement) = arrangement {
+                    app.tile_set_arrangement(tile.id, arrangement)?;
+                    tile.arrangement = arrangement;
                 }
+                if let Some(min_cell) = min_cell {
+                    tile.layout.min_cell = min_cell;
+                    app.tile_set_layout(tile.id, tile.layout)?;
+                }
+                if let Some(filter) = filter {
+                    config.tile.filter = filter;
+                }
+                displays(&mut app)?;
+                eprintln!("Building {}...", tile.name);
+                let build = app.tile_build(tile.id, &cdragon, &config)?;
+                emit(
+                    format,
+                    "tile build",
+                    &BuildOutput::new(&app, build, &cdragon)?,
+                )?;
             }
-            TileCommand::Build => todo!(),
-            TileCommand::Delete => {
-                let tiles = app.tiles.clone();
-                let tile_names: Vec<String> = tiles.iter().map(|tile| tile.name.clone()).collect();
-                if tile_names.is_empty() {
+            TileCommand::Delete { tiles, all } => {
+                if app.tiles.is_empty() {
                     return Err(anyhow!("You haven't created any tiles yet!"));
                 }
-                let selected_tiles = MultiSelect::new()
-                    .with_prompt("Which tiles do you want to delete?")
-                    .report(false)
-                    .items(&tile_names)
-                    .interact_opt()
-                    .unwrap();
-                if let Some(selected_indicies) = selected_tiles {
-                    for index in selected_indicies {
-                        app.tile_delete(tiles[index].id)?;
+                let doomed: Vec<(Uuid, String)> = if all || !tiles.is_empty() {
+                    let doomed = match all {
+                        true => app
+                            .tiles
+                            .iter()
+                            .map(|tile| (tile.id, tile.name.clone()))
+                            .collect(),
+                        false => tiles
+                            .iter()
+                            .map(|tile| {
+                                let tile = app.tile_find(Some(tile))?;
+                                Ok((tile.id, tile.name.clone()))
+                            })
+                            .collect::<anyhow::Result<Vec<(Uuid, String)>>>()?,
+                    };
+                    let names: Vec<&str> = doomed.iter().map(|tile| tile.1.as_str()).collect();
+                    if !confirm(cli.yes, &format!("Delete {}?", names.join(", ")))? {
+                        return Ok(());
+                    }
+                    doomed
+                } else {
+                    require_terminal("pass --tile or --all to delete tiles without prompting")?;
+                    let tile_names: Vec<String> =
+                        app.tiles.iter().map(|tile| tile.name.clone()).collect();
+                    MultiSelect::new()
+                        .with_prompt("Which tiles do you want to delete?")
+                        .report(false)
+                        .items(&tile_names)
+                        .interact_opt()?
+                        .unwrap_or_default()
+                        .into_iter()
+                        .map(|index| (app.tiles[index].id, app.tiles[index].name.clone()))
+                        .collect()
+                };
+                let deleted: Vec<TileSummary> = doomed
+                    .iter()
+                    .map(|(id, _)| {
+                        Ok(TileSummary::new(
+                            &app,
+                            app.tile_find(Some(&id.to_string()))?,
+                        ))
+                    })
+                    .collect::<anyhow::Result<Vec<TileSummary>>>()?;
+                for (id, _) in doomed {
+                    app.tile_delete(id)?;
+                }
+                emit(format, "tile delete", &deleted)?;
+            }
+            TileCommand::Add {
+                query,
+                ids,
+                all,
+                tile,
+            } => {
+                let tile_id = app.tile_find(tile.as_deref())?.id;
+                let selected: Vec<u64> = if !ids.is_empty() {
+                    let matches: Option<HashSet<u64>> = match query {
+                        Some(query) => {
+                            Some(cdragon.query(query)?.iter().map(|skin| skin.id).collect())
+                        }
+                        None => None,
+                    };
+                    for id in &ids {
+                        if cdragon.skin(*id).is_none() {
+                            return Err(anyhow!("skin {id} not found"));
+                        }
+                        if matches
+                            .as_ref()
+                            .is_some_and(|matches| !matches.contains(id))
+                        {
+                            return Err(anyhow!("skin {id} doesn't match the query"));
+                        }
+                    }
+                    ids
+                } else {
+                    let query = query.ok_or(anyhow!("pass a query or --ids"))?;
+                    let result_splashes = cdragon.query(query)?;
+                    match all {
+                        true => result_splashes.iter().map(|skin| skin.id).collect(),
+                        false => {
+                            require_terminal("pass --ids or --all to add skins without prompting")?;
+                            let displayed_items: Vec<String> = result_splashes
+                                .iter()
+                                .map(|skin| skin.name.clone())
+                                .collect();
+                            MultiSelect::new()
+                                .with_prompt("Select some skins using [space] and complete the selection with [enter]. Pressing [enter] without selecting any skins will use them all!")
+                                .report(false)
+                                .items(&displayed_items)
+                                .interact_opt()?
+                                .unwrap_or_default()
+                                .into_iter()
+                                .map(|index| result_splashes[index].id)
+                                .collect()
+                        }
                     }
+                };
+                for id in selected {
+                    app.tile_add_splash(&tile_id, &id)?;
                 }
+                let tile = TileSummary::new(&app, app.tile_find(Some(&tile_id.to_string()))?);
+                emit(format, "tile add", &tile)?;
             }
-            TileCommand::Add { query } => {
-                let result_splashes = cdragon.query(query)?;
-                let displayed_items: Vec<String> = result_splashes
-                    .iter()
-                    .map(|skin| skin.name.clone())
-                    .collect();
-                let selected_tile = app.selected_tile;
-                let _ = MultiSelect::new()
-                            .with_prompt("Select some skins using [space] and complete the selection with [enter]. Pressing [enter] without selecting any skins will use them all!")
-                            .report(false)
-                            .items(&displayed_items)
-                            .interact_opt()
-                            .unwrap()
-                    .map(|selected| {
-                        selected.iter().for_each(|selected_index| {
-                            app.tile_add_splash(&selected_tile, &result_splashes[*selected_index].id).unwrap_or_else(|err| panic!("{err}"));
-
-                        })
-                    });
-            }
-            TileCommand::New { name } => {
-                let name = name.unwrap_or_else(|| {
-                    Input::new()
-                        .with_prompt("What do you want to call it?")
-                        .interact()
-                        .unwrap()
-                });
-                let id = app.tile_new(name)?;
+            TileCommand::New { name, query } => {
+                let name = match name {
+                    Some(name) => name,
+                    None => {
+                        require_terminal("pass a name for the tile")?;
+                        Input::new()
+                            .with_prompt("What do you want to call it?")
+                            .interact()?
+                    }
+                };
+                let smart = !query.is_empty();
+                let mut tile = match smart {
+                    true => TileInstance::with_query(name, query),
+                    false => TileInstance::new(name),
+                };
+                tile.arrangement = config.tile.arrangement;
+                tile.layout = config.tile.layout();
+                let id = app.tile_insert(tile)?;
+                if smart {
+                    app.tile_refresh(id, &cdragon)?;
+                }
+                app.tile_select(id)?;
+                let tile = TileSummary::new(&app, app.tile_find(Some(&id.to_string()))?);
+                emit(format, "tile new", &tile)?;
+            }
+            TileCommand::Refresh { tile } => {
+                let id = app.tile_find(tile.as_deref())?.id;
+                let diff = app.tile_refresh(id, &cdragon)?;
+                emit(format, "tile refresh", &RefreshResult::new(&diff, &cdragon))?;
+            }
+            TileCommand::Rename { tile, name } => {
+                let id = app.tile_find(Some(&tile))?.id;
+                app.tile_update_name(id, name)?;
+                let tile = TileSummary::new(&app, app.tile_find(Some(&id.to_string()))?);
+                emit(format, "tile rename", &tile)?;
+            }
+            TileCommand::Select { tile } => {
+                let id = app.tile_find(Some(&tile))?.id;
                 app.tile_select(id)?;
+                let tile = TileSummary::new(&app, app.tile_find(None)?);
+                emit(format, "tile select", &tile)?;
+            }
+            TileCommand::Show { tile } => {
+                let details = TileDetails::new(&app, app.tile_find(tile.as_deref())?, &cdragon);
+                emit(format, "tile show", &details)?;
+            }
+            TileCommand::Remove { ids, tile } => {
+                let tile = app.tile_find(tile.as_deref())?.clone();
+                let ids: HashSet<u64> = match ids.is_empty() {
+                    false => ids.into_iter().collect(),
+                    true => {
+                        require_terminal("pass --ids to remove skins without prompting")?;
+                        let details = TileDetails::new(&app, &tile, &cdragon);
+                        let mut items: Vec<(u64, String)> = details
+                            .skins
+                            .iter()
+                            .map(|skin| (skin.id, skin.name.clone()))
+                            .collect();
+                        items.extend(details.missing.iter().map(|id| (*id, id.to_string())));
+                        let names: Vec<&str> = items.iter().map(|item| item.1.as_str()).collect();
+                        MultiSelect::new()
+                            .with_prompt("Which skins do you want to remove?")
+                            .report(false)
+                            .items(&names)
+                            .interact_opt()?
+                            .unwrap_or_default()
+                            .into_iter()
+                            .map(|index| items[index].0)
+                            .collect()
+                    }
+                };
+                if let Some(id) = ids.iter().find(|id| !tile.splash_ids.contains(id)) {
+                    return Err(anyhow!("skin {id} isn't in {}", tile.name));
+                }
+                app.tile_remove_splashes(tile.id, &ids)?;
+                let tile = TileSummary::new(&app, app.tile_find(Some(&tile.id.to_string()))?);
+                emit(format, "tile remove", &tile)?;
+            }
+            TileCommand::Clear { tile } => {
+                let tile = app.tile_find(tile.as_deref())?.clone();
+                if !confirm(
+                    cli.yes,
+                    &format!(
+                        "Remove all {} skins from {}?",
+                        tile.splash_ids.len(),
+                        tile.name
+                    ),
+                )? {
+                    return Ok(());
+                }
+                app.tile_clear(tile.id)?;
+                let tile = TileSummary::new(&app, app.tile_find(Some(&tile.id.to_string()))?);
+                emit(format, "tile clear", &tile)?;
+            }
+            TileCommand::Duplicate { tile, name } => {
+                let tile = app.tile_find(Some(&tile))?;
+                let name = name.unwrap_or_else(|| format!("{} copy", tile.name));
+                let id = app.tile_duplicate(tile.id, name)?;
+                let tile = TileSummary::new(&app, app.tile_find(Some(&id.to_string()))?);
+                emit(format, "tile duplicate", &tile)?;
+            }
+            TileCommand::Export { path, tile } => {
+                let tile = app.tile_find(tile.as_deref())?;
+                Preset::from_tile(tile, &cdragon).save(&path)?;
+                let tile = TileSummary::new(&app, tile);
+                emit(format, "tile export", &ExportResult { tile, path })?;
+            }
+            TileCommand::Import { path, name } => {
+                let mut preset = Preset::load(&path)?;
+                if let Some(name) = name {
+                    preset.name = name;
+                }
+                let (tile, missing) = preset.into_tile(&cdragon)?;
+                let id = app.tile_insert(tile)?;
+                let tile = TileSummary::new(&app, app.tile_find(Some(&id.to_string()))?);
+                emit(format, "tile import", &ImportResult { tile, missing })?;
             }
         },
-        Commands::Download { query } => cdragon.query(query)?.into_par_iter().for_each(|skin| {
-            CDragon::download_splash(skin, &app.download_path).unwrap_or_else(|e| panic!("{e}"))
-        }),
+        Commands::Sync { dry_run } => {
+            let report = app.sync(&cdragon, &config, dry_run)?;
+            emit(format, "sync", &SyncResult::new(report, &cdragon))?;
+        }
+        Commands::WhatsNew { last, tile } => {
+            let changelog = Changelog::read()?;
+            let entries: Vec<ChangelogEntry> =
+                changelog.latest(last).into_iter().cloned().collect();
+            let tile = match tile {
+                Some(name) => {
+                    let ids: Vec<u64> = entries
+                        .iter()
+                        .flat_map(|entry| &entry.new_skins)
+                        .map(|skin| skin.id)
+                        .filter(|id| cdragon.skin(*id).is_some())
+                        .collect();
+                    Some(new_tile_with(&mut app, &config, name, &ids)?)
+                }
+                None => None,
+            };
+            emit(format, "whats-new", &WhatsNew { entries, tile })?;
+        }
+        Commands::Patch(PatchCommand::List) => {
+            let mut patches = vec![LATEST_PATCH.to_string()];
+            patches.extend(CDragon::patches()?);
+            let current = config.source.patch.clone();
+            emit(format, "patch list", &PatchList { current, patches })?;
+        }
+        Commands::Patch(PatchCommand::Diff { to, from, tile }) => {
+            let from = match from {
+                Some(from) => from,
+                None => previous_patch(&to)?,
+            };
+            let newer = CDragon::at_patch(&config, &to)?;
+            let older = CDragon::at_patch(&config, &from)?;
+            let skins = newer.new_skins(&older);
+            let tile = match tile {
+                Some(name) => {
+                    let ids: Vec<u64> = skins.iter().map(|skin| skin.id).collect();
+                    Some(new_tile_with(&mut app, &config, name, &ids)?)
+                }
+                None => None,
+            };
+            let diff = PatchDiff {
+                from,
+                to,
+                skins: skins.into_iter().map(SkinSummary::from).collect(),
+                tile,
+            };
+            emit(format, "patch diff", &diff)?;
+        }
+        Commands::Download { query } => {
+            let downloads = app.download(cdragon.query(query)?, &cdragon, &config)?;
+            emit(
+                format,
+                "download",
+                &DownloadResult::all(&downloads, &cdragon),
+            )?;
+        }
+        Commands::Wallpaper(WallpaperCommand::Set {
+            tile,
+            backend,
+            dry_run,
+            files,
+        }) => {
+            let explicit = !files.is_empty();
+            let files = match explicit {
+                true => files,
+                false => {
+                    let tile = app.tile_find(tile.as_deref())?;
+                    app.built_files(tile)
+                }
+            };
+            // outputs are named after the displays they go on when there are any to ask
+            let displays = match (dry_run, explicit) {
+                (true, _) => Vec::new(),
+                (false, true) => DisplayInfo::all().unwrap_or_default(),
+                (false, false) => displays(&mut app)?,
+            };
+            let outputs = files
+                .iter()
+                .enumerate()
+                .map(|(index, file)| {
+                    let wallpaper = file.canonicalize().with_context(|| {
+                        format!("{:?} doesn't exist, build the tile first", file)
+                    })?;
+                    let name = displays
+                        .get(index)
+                        .map(|display| display.name.clone())
+                        .unwrap_or_else(|| index.to_string());
+                    Ok(Output { name, wallpaper })
+                })
+                .collect::<anyhow::Result<Vec<Output>>>()?;
+            let backend = backend
+                .or(config.wallpaper.backend)
+                .or_else(|| BackendKind::detect(&Environment::from_env()))
+                .ok_or(anyhow!(
+                    "couldn't detect how to set the wallpaper, pick one with --backend"
+                ))?
+                .backend();
+            let commands = backend
+                .apply(&outputs, dry_run)?
+                .iter()
+                .map(|command| command.to_string())
+                .collect();
+            let result = WallpaperResult {
+                backend: format!("{:?}", backend.kind()).to_lowercase(),
+                dry_run,
+                commands,
+            };
+            emit(format, "wallpaper set", &result)?;
+        }
+        Commands::Browse { tile } => {
+            if !io::stdout().is_terminal() {
+                return Err(anyhow!(
+                    "stdout isn't a terminal, browse needs a full screen"
+                ));
+            }
+            require_terminal("browse needs a keyboard")?;
+            let id = app.tile_find(tile.as_deref())?.id;
+            displays(&mut app)?;
+            tui::run(&mut app, &cdragon, &config, id)?;
+            let tile = TileSummary::new(&app, app.tile_find(Some(&id.to_string()))?);
+            emit(format, "browse", &tile)?;
+        }
+        Commands::Preview {
+            ids,
+            query,
+            tile,
+            protocol,
+            open,
+            width,
+        } => {
+            let mut ids = match query {
+                Some(query) => cdragon.query(query)?.iter().map(|skin| skin.id).collect(),
+                None => ids,
+            };
+            let (files, skins) = match ids.is_empty() {
+                true => {
+                    let tile = app.tile_find(tile.as_deref())?;
+                    let files = app.built_files(tile);
+                    if let Some(missing) = files.iter().find(|file| !file.exists()) {
+                        return Err(anyhow!("{:?} doesn't exist, build the tile first", missing));
+                    }
+                    (files, Vec::new())
+                }
+                false => {
+                    ids.sort();
+                    ids.dedup();
+                    download_missing(&mut app, &cdragon, &ids, &config)?;
+                    ids.retain(|id| app.is_downloaded(*id, &cdragon.patch));
+                    let splash_paths: Vec<PathBuf> = ids
+                        .iter()
+                        .map(|id| app.splash_path(*id, &cdragon.patch))
+                        .collect();
+                    let file = match splash_paths.as_slice() {
+                        [] => return Err(anyhow!("none of the skins could be downloaded")),
+                        [path] => path.clone(),
+                        _ => {
+                            let dir = paths::get()?.ensure(Location::Cache)?.join("previews");
+                            fs::create_dir_all(&dir)?;
+                            let path = dir.join("contact-sheet.jpg");
+                            contact_sheet(&splash_paths, THUMBNAIL)?
+                                .save(&path)
+                                .with_context(|| format!("failed to save {:?}", path))?;
+                            path
+                        }
+                    };
+                    let skins = ids
+                        .iter()
+                        .filter_map(|id| cdragon.skin(*id))
+                        .map(SkinSummary::from)
+                        .collect();
+                    (vec![file], skins)
+                }
+            };
+            if open {
+                for file in &files {
+                    open::that(file).with_context(|| format!("failed to open {:?}", file))?;
+                }
+            } else if format != OutputFormat::Json {
+                if !io::stdout().is_terminal() {
+                    return Err(anyhow!(
+                        "stdout isn't a terminal, pass --open to use an image viewer"
+                    ));
+                }
+                let terminal = Terminal::from_env();
+                let protocol = protocol.unwrap_or_else(|| Protocol::detect(&terminal));
+                let columns = width.unwrap_or(terminal.columns);
+                let rows = terminal.rows.saturating_sub(2).max(4);
+                for file in &files {
+                    print!("{}", preview::show(file, protocol, columns, rows)?);
+                }
+            }
+            emit(format, "preview", &PreviewResult { files, skins })?;
+        }
+        Commands::Config(_) => unreachable!("config commands run before anything is loaded"),
+        Commands::Cache(_) => unreachable!("cache commands run before anything is loaded"),
+        Commands::Rotate {
+            count,
+            tile,
+            query,
+            per_wallpaper,
+            arrange: arrangement,
+            filter,
+            seed,
+            out,
+        } => {
+            let arrangement = arrangement.unwrap_or(config.tile.arrangement);
+            let (name, mut ids, layout): (String, Vec<u64>, TileLayout) = match query {
+                Some(query) => (
+                    query.clone(),
+                    cdragon.query(query)?.iter().map(|skin| skin.id).collect(),
+                    config.tile.layout(),
+                ),
+                None => {
+                    let id = app.tile_find(tile.as_deref())?.id;
+                    app.tile_refresh(id, &cdragon)?;
+                    let tile = app.tile_find(Some(&id.to_string()))?;
+                    (tile.name.clone(), tile.splash_ids.clone(), tile.layout)
+                }
+            };
+            ids.sort();
+            displays(&mut app)?;
+            download_missing(&mut app, &cdragon, &ids, &config)?;
+            ids.retain(|id| app.is_downloaded(*id, &cdragon.patch));
+            if arrangement != Arrangement::None {
+                app.analyze_splashes(&ids, &cdragon.patch)?;
+            }
+
+            let mut rotation = Rotation::new(&name, count);
+            rotation.per_wallpaper = per_wallpaper;
+            rotation.arrangement = arrangement;
+            rotation.layout = layout;
+            rotation.build = config.tile.build_options();
+            if let Some(filter) = filter {
+                rotation.build.resize.filter = filter;
+            }
+            if let Some(seed) = seed {
+                rotation.seed = seed;
+            }
+            let out = out.unwrap_or_else(|| {
+                app.tile_path
+                    .join("rotations")
+                    .join(paths::file_name(&name))
+            });
+            eprintln!("Building {count} wallpapers for {name}...");
+            let manifest = rotation.generate(&ids, &app, &cdragon, &out)?;
+            emit(format, "rotate", &manifest)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn config_command(command: ConfigCommand, format: OutputFormat) -> anyhow::Result<()> {
+    let entry = |config: &Config, key: &str| -> anyhow::Result<ConfigEntry> {
+        Ok(ConfigEntry::new(key, config.get(key)?))
+    };
+    match command {
+        ConfigCommand::Get { key } => emit(format, "config get", &entry(&Config::load()?, &key)?),
+        ConfigCommand::Set { key, value } => emit(
+            format,
+            "config set",
+            &entry(&Config::set(&key, &value)?, &key)?,
+        ),
+        ConfigCommand::Unset { key } => {
+            emit(format, "config unset", &entry(&Config::unset(&key)?, &key)?)
+        }
+        ConfigCommand::List => {
+            let entries: Vec<ConfigEntry> = Config::load()?
+                .list()?
+                .into_iter()
+                .map(|(key, value)| ConfigEntry::new(key, value))
+                .collect();
+            emit(format, "config list", &entries)
+        }
+    }
+}
+
+/// Fail instead of prompting when stdin isn't a terminal, so scripts don't hang
+/// The connected displays, whose sizes the app builds for unless it already knows its monitors
+fn displays(app: &mut App) -> anyhow::Result<Vec<DisplayInfo>> {
+    let displays = DisplayInfo::all().with_context(|| "failed to get display info")?;
+    app.remember_monitors(
+        displays
+            .iter()
+            .map(|display| (display.width, display.height))
+            .collect(),
+    )?;
+    Ok(displays)
+}
+
+fn require_terminal(hint: &str) -> anyhow::Result<()> {
+    match io::stdin().is_terminal() {
+        true => Ok(()),
+        false => Err(anyhow!("stdin isn't a terminal, {hint}")),
     }
+}
+
+fn confirm(yes: bool, prompt: &str) -> anyhow::Result<bool> {
+    if yes {
+        return Ok(true);
+    }
+    require_terminal("pass --yes to confirm without prompting")?;
+    Ok(Confirm::new()
+        .with_prompt(prompt)
+        .default(false)
+        .interact()?)
+}
+
+/// Create and select a tile of `ids`, laid out as the config says
+fn new_tile_with(
+    app: &mut App,
+    config: &Config,
+    name: String,
+    ids: &[u64],
+) -> anyhow::Result<TileSummary> {
+    let mut tile = TileInstance::new(name);
+    tile.arrangement = config.tile.arrangement;
+    tile.layout = config.tile.layout();
+    tile.add_splashes(ids);
+    let id = app.tile_insert(tile)?;
+    app.tile_select(id)?;
+    Ok(TileSummary::new(app, app.tile_find(Some(&id.to_string()))?))
+}
+
+/// The patch released before `patch`, where latest counts as the newest numbered patch
+fn previous_patch(patch: &str) -> anyhow::Result<String> {
+    let patches = CDragon::patches()?;
+    let index = match patch {
+        LATEST_PATCH => 0,
+        patch => patches
+            .iter()
+            .position(|known| known == patch)
+            .ok_or(anyhow!(
+                "there's no patch {patch}, run `patch list` to see them"
+            ))?,
+    };
+    patches
+        .get(index + 1)
+        .cloned()
+        .ok_or(anyhow!("there's no patch before {patch}"))
+}
 
+/// Download any of `ids` that haven't been downloaded yet, failures are reported on stderr
+fn download_missing(
+    app: &mut App,
+    cdragon: &CDragon,
+    ids: &[u64],
+    config: &Config,
+) -> anyhow::Result<()> {
+    for result in DownloadResult::all(&app.download_missing(cdragon, ids, config)?, cdragon) {
+        if let Some(error) = result.error {
+            eprintln!("failed to download {}: {error}", result.skin.name);
+        }
+    }
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use clap::CommandFactory;
+
+    use super::Cli;
+
+    #[test]
+    fn valid_arguments() {
+        Cli::command().debug_assert();
+    }
+}
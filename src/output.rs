@@ -0,0 +1,795 @@
+use std::path::PathBuf;
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    app::{App, BuiltFile, Download, QueryDiff, SyncReport, TileBuild, TileInstance, TileSource},
+    cdragon::{CDragon, Skin, SkinQuery},
+    changelog::{ChangelogEntry, Named},
+    config::KEYS,
+    palette::Arrangement,
+    preset::PresetSkin,
+    rotate::RotationManifest,
+    tiled_splash::MB,
+};
+
+/// The version of the JSON output schema
+///
+/// Bumped whenever a field is removed or changes meaning, adding fields doesn't bump it.
+pub const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputFormat {
+    /// Human readable lines
+    #[default]
+    Plain,
+    /// Aligned columns
+    Table,
+    /// A JSON document wrapped in a versioned envelope
+    Json,
+}
+
+/// Every JSON document is wrapped in this, so tooling can check the schema before reading `data`
+#[derive(Debug, Serialize)]
+pub struct Envelope<'a, T: Serialize + ?Sized> {
+    pub schema_version: u32,
+    pub command: &'a str,
+    pub data: &'a T,
+}
+
+/// A command result that can be printed in every `OutputFormat`
+pub trait Render: Serialize {
+    fn plain(&self) -> String;
+
+    fn table(&self) -> String {
+        self.plain()
+    }
+
+    fn render(&self, format: OutputFormat, command: &str) -> anyhow::Result<String> {
+        Ok(match format {
+            OutputFormat::Plain => self.plain(),
+            OutputFormat::Table => self.table(),
+            OutputFormat::Json => serde_json::to_string_pretty(&Envelope {
+                schema_version: SCHEMA_VERSION,
+                command,
+                data: self,
+            })?,
+        })
+    }
+}
+
+/// Print a command result to stdout, nothing is printed for empty plain output
+pub fn emit<T: Render>(format: OutputFormat, command: &str, result: &T) -> anyhow::Result<()> {
+    let rendered = result.render(format, command)?;
+    if !rendered.is_empty() {
+        println!("{rendered}");
+    }
+    Ok(())
+}
+
+/// Lay out rows in columns padded to the widest cell
+pub fn table(headers: &[&str], rows: &[Vec<String>]) -> String {
+    let mut widths: Vec<usize> = headers.iter().map(|header| header.len()).collect();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let line = |cells: Vec<&str>| {
+        cells
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{cell:<width$}"))
+            .collect::<Vec<String>>()
+            .join("  ")
+            .trim_end()
+            .to_string()
+    };
+    let mut lines = vec![line(headers.to_vec())];
+    lines.extend(
+        rows.iter()
+            .map(|row| line(row.iter().map(String::as_str).collect())),
+    );
+    lines.join("\n")
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TileSummary {
+    pub id: Uuid,
+    pub name: String,
+    pub selected: bool,
+    pub splash_count: usize,
+    pub splash_ids: Vec<u64>,
+    pub arrangement: Arrangement,
+    /// Set for smart tiles
+    pub query: Option<SkinQuery>,
+    /// Whether splashes in the tile changed since it was built
+    pub needs_rebuild: bool,
+}
+
+impl TileSummary {
+    pub fn new(app: &App, tile: &TileInstance) -> Self {
+        let splash_ids = tile.splash_ids.clone();
+        Self {
+            id: tile.id,
+            name: tile.name.clone(),
+            selected: tile.id == app.selected_tile,
+            splash_count: splash_ids.len(),
+            splash_ids,
+            arrangement: tile.arrangement,
+            query: match &tile.source {
+                TileSource::Static => None,
+                TileSource::Query(query) => Some(query.clone()),
+            },
+            needs_rebuild: tile.needs_rebuild,
+        }
+    }
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            if self.selected { "*" } else { "" }.to_string(),
+            self.name.clone(),
+            self.splash_count.to_string(),
+            self.id.to_string(),
+        ]
+    }
+}
+
+impl Render for TileSummary {
+    fn plain(&self) -> String {
+        self.name.clone()
+    }
+
+    fn table(&self) -> String {
+        table(&["", "NAME", "SPLASHES", "ID"], &[self.row()])
+    }
+}
+
+impl Render for Vec<TileSummary> {
+    fn plain(&self) -> String {
+        self.iter()
+            .map(|tile| {
+                let marker = if tile.selected { "*" } else { " " };
+                match tile.needs_rebuild {
+                    true => format!("{marker} {} (needs rebuild)", tile.name),
+                    false => format!("{marker} {}", tile.name),
+                }
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    fn table(&self) -> String {
+        let rows: Vec<Vec<String>> = self.iter().map(TileSummary::row).collect();
+        table(&["", "NAME", "SPLASHES", "ID"], &rows)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SkinSummary {
+    pub id: u64,
+    pub name: String,
+    pub champion_id: u64,
+    pub rarity: String,
+    pub is_base: bool,
+    pub is_legacy: bool,
+    pub skin_lines: Vec<String>,
+}
+
+impl From<&Skin> for SkinSummary {
+    fn from(skin: &Skin) -> Self {
+        Self {
+            id: skin.id,
+            name: skin.name.clone(),
+            champion_id: skin.champion_id(),
+            rarity: skin.rarity.clone(),
+            is_base: skin.is_base,
+            is_legacy: skin.is_legacy,
+            skin_lines: skin
+                .skin_lines
+                .iter()
+                .flatten()
+                .map(|line| line.name.clone())
+                .filter(|name| !name.is_empty())
+                .collect(),
+        }
+    }
+}
+
+impl SkinSummary {
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.id.to_string(),
+            self.name.clone(),
+            self.rarity.clone(),
+            self.skin_lines.join(", "),
+        ]
+    }
+}
+
+/// A tile with its splashes resolved to skins
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TileDetails {
+    pub tile: TileSummary,
+    pub skins: Vec<SkinSummary>,
+    /// Splash ids that aren't in the skin data anymore
+    pub missing: Vec<u64>,
+}
+
+impl TileDetails {
+    pub fn new(app: &App, tile: &TileInstance, cdragon: &CDragon) -> Self {
+        let tile = TileSummary::new(app, tile);
+        let mut skins = Vec::new();
+        let mut missing = Vec::new();
+        for id in &tile.splash_ids {
+            match cdragon.skin(*id) {
+                Some(skin) => skins.push(SkinSummary::from(skin)),
+                None => missing.push(*id),
+            }
+        }
+        Self {
+            tile,
+            skins,
+            missing,
+        }
+    }
+}
+
+impl Render for TileDetails {
+    fn plain(&self) -> String {
+        let mut lines = vec![format!(
+            "{} ({} splashes)",
+            self.tile.name, self.tile.splash_count
+        )];
+        lines.extend(self.skins.iter().map(|skin| format!("  {}", skin.name)));
+        lines.extend(
+            self.missing
+                .iter()
+                .map(|id| format!("  {id} (unknown skin)")),
+        );
+        lines.join("\n")
+    }
+
+    fn table(&self) -> String {
+        let mut rows: Vec<Vec<String>> = self.skins.iter().map(SkinSummary::row).collect();
+        rows.extend(
+            self.missing
+                .iter()
+                .map(|id| vec![id.to_string(), String::from("(unknown skin)")]),
+        );
+        table(&["ID", "NAME", "RARITY", "SKIN LINES"], &rows)
+    }
+}
+
+/// How a smart tile changed when its query was re-run
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RefreshResult {
+    pub added: Vec<SkinSummary>,
+    pub removed: Vec<u64>,
+}
+
+impl RefreshResult {
+    pub fn new(diff: &QueryDiff, cdragon: &CDragon) -> Self {
+        Self {
+            added: diff
+                .added
+                .iter()
+                .filter_map(|id| cdragon.skin(*id))
+                .map(SkinSummary::from)
+                .collect(),
+            removed: diff.removed.clone(),
+        }
+    }
+}
+
+impl Render for RefreshResult {
+    fn plain(&self) -> String {
+        let mut lines: Vec<String> = self
+            .added
+            .iter()
+            .map(|skin| format!("+ {}", skin.name))
+            .collect();
+        lines.extend(self.removed.iter().map(|id| format!("- {id}")));
+        lines.join("\n")
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportResult {
+    pub tile: TileSummary,
+    pub path: PathBuf,
+}
+
+impl Render for ExportResult {
+    fn plain(&self) -> String {
+        self.path.display().to_string()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigEntry {
+    pub key: String,
+    /// `None` when the key isn't set and has no default
+    pub value: Option<String>,
+    pub description: String,
+}
+
+impl ConfigEntry {
+    pub fn new(key: &str, value: Option<String>) -> Self {
+        Self {
+            key: key.to_string(),
+            value,
+            description: KEYS
+                .iter()
+                .find(|(known, _)| *known == key)
+                .map(|(_, description)| description.to_string())
+                .unwrap_or_default(),
+        }
+    }
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.key.clone(),
+            self.value.clone().unwrap_or_default(),
+            self.description.clone(),
+        ]
+    }
+}
+
+impl Render for ConfigEntry {
+    fn plain(&self) -> String {
+        self.value.clone().unwrap_or_default()
+    }
+
+    fn table(&self) -> String {
+        table(&["KEY", "VALUE", "DESCRIPTION"], &[self.row()])
+    }
+}
+
+impl Render for Vec<ConfigEntry> {
+    fn plain(&self) -> String {
+        self.iter()
+            .map(|entry| match &entry.value {
+                Some(value) => format!("{} = {value}", entry.key),
+                None => format!("{} is unset", entry.key),
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    fn table(&self) -> String {
+        let rows: Vec<Vec<String>> = self.iter().map(ConfigEntry::row).collect();
+        table(&["KEY", "VALUE", "DESCRIPTION"], &rows)
+    }
+}
+
+/// What `cache clear` removed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CacheClearResult {
+    pub cells: usize,
+    pub bytes: u64,
+}
+
+impl Render for CacheClearResult {
+    fn plain(&self) -> String {
+        format!(
+            "Removed {} cells, {:.1} MB",
+            self.cells,
+            self.bytes as f64 / MB as f64
+        )
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImportResult {
+    pub tile: TileSummary,
+    /// Skins in the preset that aren't in the local skin data
+    pub missing: Vec<PresetSkin>,
+}
+
+impl Render for ImportResult {
+    fn plain(&self) -> String {
+        let mut lines = vec![format!(
+            "Imported {} with {} splashes",
+            self.tile.name, self.tile.splash_count
+        )];
+        lines.extend(
+            self.missing
+                .iter()
+                .map(|skin| format!("  skipped {} ({}), it no longer exists", skin.name, skin.id)),
+        );
+        lines.join("\n")
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DownloadResult {
+    pub skin: SkinSummary,
+    pub path: PathBuf,
+    /// Why the download failed, `None` when it succeeded
+    pub error: Option<String>,
+}
+
+impl DownloadResult {
+    /// `None` when the skin isn't in `cdragon`
+    pub fn new(download: &Download, cdragon: &CDragon) -> Option<Self> {
+        Some(Self {
+            skin: SkinSummary::from(cdragon.skin(download.id)?),
+            path: download.path.clone(),
+            error: download.error.clone(),
+        })
+    }
+
+    pub fn all(downloads: &[Download], cdragon: &CDragon) -> Vec<Self> {
+        downloads
+            .iter()
+            .filter_map(|download| Self::new(download, cdragon))
+            .collect()
+    }
+}
+
+impl Render for Vec<DownloadResult> {
+    fn plain(&self) -> String {
+        self.iter()
+            .map(|result| match &result.error {
+                None => format!("{} -> {}", result.skin.name, result.path.display()),
+                Some(error) => format!("{} failed: {error}", result.skin.name),
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    fn table(&self) -> String {
+        let rows: Vec<Vec<String>> = self
+            .iter()
+            .map(|result| {
+                vec![
+                    result.skin.id.to_string(),
+                    result.skin.name.clone(),
+                    match &result.error {
+                        None => result.path.display().to_string(),
+                        Some(error) => format!("failed: {error}"),
+                    },
+                ]
+            })
+            .collect();
+        table(&["ID", "NAME", "RESULT"], &rows)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BuildOutput {
+    pub tile: TileSummary,
+    /// Set when a smart tile's query matched different skins than last time
+    pub refreshed: Option<RefreshResult>,
+    pub files: Vec<BuiltFile>,
+    /// Splashes that couldn't be downloaded and were left out
+    pub failed: Vec<DownloadResult>,
+}
+
+impl BuildOutput {
+    pub fn new(app: &App, build: TileBuild, cdragon: &CDragon) -> anyhow::Result<Self> {
+        Ok(Self {
+            tile: TileSummary::new(app, app.tile_find(Some(&build.tile.to_string()))?),
+            refreshed: match build.refreshed.is_empty() {
+                true => None,
+                false => Some(RefreshResult::new(&build.refreshed, cdragon)),
+            },
+            files: build.files,
+            failed: DownloadResult::all(&build.failed, cdragon),
+        })
+    }
+}
+
+impl Render for BuildOutput {
+    fn plain(&self) -> String {
+        let mut lines: Vec<String> = self.refreshed.iter().map(RefreshResult::plain).collect();
+        lines.extend(self.files.iter().map(|file| match file.reused {
+            true => format!("{} (unchanged)", file.path.display()),
+            false => file.path.display().to_string(),
+        }));
+        if !self.failed.is_empty() {
+            lines.push(self.failed.plain());
+        }
+        lines.join("\n")
+    }
+
+    fn table(&self) -> String {
+        let rows: Vec<Vec<String>> = self
+            .files
+            .iter()
+            .map(|file| {
+                vec![
+                    format!("{}x{}", file.monitor.0, file.monitor.1),
+                    file.path.display().to_string(),
+                ]
+            })
+            .collect();
+        table(&["MONITOR", "PATH"], &rows)
+    }
+}
+
+impl Render for RotationManifest {
+    fn plain(&self) -> String {
+        self.wallpapers
+            .iter()
+            .flat_map(|wallpaper| &wallpaper.files)
+            .map(|file| file.path.display().to_string())
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    fn table(&self) -> String {
+        let rows: Vec<Vec<String>> = self
+            .wallpapers
+            .iter()
+            .flat_map(|wallpaper| {
+                wallpaper.files.iter().map(|file| {
+                    vec![
+                        wallpaper.index.to_string(),
+                        wallpaper.splash_ids.len().to_string(),
+                        file.path.display().to_string(),
+                    ]
+                })
+            })
+            .collect();
+        table(&["INDEX", "SPLASHES", "PATH"], &rows)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WallpaperResult {
+    pub backend: String,
+    pub dry_run: bool,
+    pub commands: Vec<String>,
+}
+
+impl Render for WallpaperResult {
+    fn plain(&self) -> String {
+        match self.dry_run {
+            true => self.commands.join("\n"),
+            false => String::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PreviewResult {
+    /// The images previewed, a contact sheet when there were several skins
+    pub files: Vec<PathBuf>,
+    /// The skins on the contact sheet, in the order they're laid out
+    pub skins: Vec<SkinSummary>,
+}
+
+impl Render for PreviewResult {
+    fn plain(&self) -> String {
+        self.skins
+            .iter()
+            .enumerate()
+            .map(|(index, skin)| format!("{:>3}. {} ({})", index + 1, skin.name, skin.id))
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+}
+
+/// What `sync` found and re-downloaded
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SyncResult {
+    /// How many downloaded splashes were checked
+    pub checked: usize,
+    /// Splashes whose art changed
+    pub changed: Vec<SkinSummary>,
+    /// The re-downloads, empty for a dry run
+    pub updated: Vec<DownloadResult>,
+    /// Where the replaced splashes were moved
+    pub archived: Vec<PathBuf>,
+    /// Tiles that use re-downloaded splashes
+    pub stale_tiles: Vec<String>,
+    /// Splashes that couldn't be checked
+    pub errors: Vec<String>,
+}
+
+impl SyncResult {
+    pub fn new(report: SyncReport, cdragon: &CDragon) -> Self {
+        Self {
+            checked: report.checked,
+            changed: report
+                .changed
+                .iter()
+                .filter_map(|id| cdragon.skin(*id))
+                .map(SkinSummary::from)
+                .collect(),
+            updated: DownloadResult::all(&report.updated, cdragon),
+            archived: report.archived,
+            stale_tiles: report.stale_tiles,
+            errors: report.errors,
+        }
+    }
+}
+
+impl Render for SyncResult {
+    fn plain(&self) -> String {
+        let mut lines = vec![format!(
+            "Checked {} splashes, {} changed",
+            self.checked,
+            self.changed.len()
+        )];
+        match self.updated.is_empty() {
+            true => lines.extend(
+                self.changed
+                    .iter()
+                    .map(|skin| format!("  {} ({})", skin.name, skin.id)),
+            ),
+            false => lines.push(self.updated.plain()),
+        }
+        if !self.stale_tiles.is_empty() {
+            lines.push(format!("Rebuild {}", self.stale_tiles.join(", ")));
+        }
+        lines.extend(
+            self.errors
+                .iter()
+                .map(|error| format!("couldn't check {error}")),
+        );
+        lines.join("\n")
+    }
+}
+
+/// The patches that can be pinned, newest first
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PatchList {
+    /// The patch in use, from `--patch` or source.patch in the config
+    pub current: String,
+    pub patches: Vec<String>,
+}
+
+impl Render for PatchList {
+    fn plain(&self) -> String {
+        self.patches
+            .iter()
+            .map(|patch| match *patch == self.current {
+                true => format!("* {patch}"),
+                false => format!("  {patch}"),
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+}
+
+/// The skins added between two patches
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PatchDiff {
+    pub from: String,
+    pub to: String,
+    pub skins: Vec<SkinSummary>,
+    /// The tile made from the new skins, if one was asked for
+    pub tile: Option<TileSummary>,
+}
+
+impl PatchDiff {
+    fn heading(&self) -> String {
+        format!(
+            "{} new skins in {} since {}",
+            self.skins.len(),
+            self.to,
+            self.from
+        )
+    }
+
+    fn created(&self) -> Option<String> {
+        self.tile
+            .as_ref()
+            .map(|tile| format!("Created {} with {} splashes", tile.name, tile.splash_count))
+    }
+}
+
+impl Render for PatchDiff {
+    fn plain(&self) -> String {
+        let mut lines = vec![self.heading()];
+        lines.extend(
+            self.skins
+                .iter()
+                .map(|skin| format!("  {} ({})", skin.name, skin.id)),
+        );
+        lines.extend(self.created());
+        lines.join("\n")
+    }
+
+    fn table(&self) -> String {
+        let rows: Vec<Vec<String>> = self.skins.iter().map(SkinSummary::row).collect();
+        let mut lines = vec![
+            self.heading(),
+            table(&["ID", "NAME", "RARITY", "SKIN LINES"], &rows),
+        ];
+        lines.extend(self.created());
+        lines.join("\n")
+    }
+}
+
+/// Recent changes to the skins, newest first
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WhatsNew {
+    pub entries: Vec<ChangelogEntry>,
+    /// The tile made from the new skins, if one was asked for
+    pub tile: Option<TileSummary>,
+}
+
+impl Render for WhatsNew {
+    fn plain(&self) -> String {
+        if self.entries.is_empty() {
+            return String::from("Nothing has changed since the skins were first fetched");
+        }
+        let mut lines = Vec::new();
+        for entry in &self.entries {
+            lines.push(format!(
+                "{}: {}",
+                entry.date.format("%Y-%m-%d"),
+                entry.summary()
+            ));
+            let named = |prefix: &str, named: &Vec<Named>| -> Vec<String> {
+                named
+                    .iter()
+                    .map(|named| format!("  {prefix}{} ({})", named.name, named.id))
+                    .collect()
+            };
+            lines.extend(named("new champion ", &entry.new_champions));
+            lines.extend(named("+ ", &entry.new_skins));
+            lines.extend(named("- ", &entry.removed_skins));
+            lines.extend(
+                entry
+                    .renamed_skins
+                    .iter()
+                    .map(|skin| format!("  ~ {} -> {} ({})", skin.from, skin.to, skin.id)),
+            );
+            lines.extend(named("new splash for ", &entry.updated_splashes));
+        }
+        if let Some(tile) = &self.tile {
+            lines.push(format!(
+                "Created {} with {} splashes",
+                tile.name, tile.splash_count
+            ));
+        }
+        lines.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{table, OutputFormat, Render, TileSummary, SCHEMA_VERSION};
+    use crate::{app::App, palette::Arrangement};
+
+    #[test]
+    fn aligned_table() {
+        let rendered = table(
+            &["ID", "NAME"],
+            &[
+                vec![String::from("1"), String::from("Annie")],
+                vec![String::from("1000"), String::from("Goth Annie")],
+            ],
+        );
+        assert_eq!(rendered, "ID    NAME\n1     Annie\n1000  Goth Annie");
+    }
+
+    #[test]
+    fn json_envelope() {
+        let mut app = App::default();
+        let id = app.tile_new("testy").unwrap();
+        let summary = TileSummary::new(&app, app.tile_find(Some("testy")).unwrap());
+        let json: serde_json::Value = serde_json::from_str(
+            &vec![summary]
+                .render(OutputFormat::Json, "tile list")
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(json["schema_version"], SCHEMA_VERSION);
+        assert_eq!(json["command"], "tile list");
+        assert_eq!(json["data"][0]["id"], id.to_string());
+        assert_eq!(json["data"][0]["selected"], false);
+        assert_eq!(
+            json["data"][0]["arrangement"],
+            serde_json::to_value(Arrangement::None).unwrap()
+        );
+    }
+}
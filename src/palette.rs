@@ -0,0 +1,230 @@
+use std::{collections::HashMap, path::Path};
+
+use anyhow::Context;
+use clap::ValueEnum;
+use image::DynamicImage;
+use serde::{Deserialize, Serialize};
+
+/// The side length the splash is shrunk to before sampling its colors
+const SAMPLE_SIZE: u32 = 64;
+/// How many of the most common color buckets are kept as dominant colors
+const DOMINANT_COUNT: usize = 3;
+
+/// A summary of the colors in a splash, used to arrange tiles so neighbouring cells don't clash
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SplashColors {
+    /// The mean color of the whole splash
+    pub average: [u8; 3],
+    /// The relative luminance of the average color, from 0.0 (black) to 1.0 (white)
+    pub luminance: f32,
+    /// The most common colors, most frequent first
+    pub dominant: Vec<[u8; 3]>,
+}
+
+impl SplashColors {
+    pub fn analyze(path: &Path) -> anyhow::Result<Self> {
+        let image =
+            image::open(path).with_context(|| format!("failed to open splash {:?}", path))?;
+        Ok(Self::from_image(&image))
+    }
+
+    pub fn from_image(image: &DynamicImage) -> Self {
+        let sample = image.thumbnail(SAMPLE_SIZE, SAMPLE_SIZE).to_rgb8();
+        let pixel_count = (sample.width() * sample.height()).max(1) as u64;
+
+        let mut sum = [0u64; 3];
+        // 3 bits per channel, 512 buckets of (pixel count, channel sums)
+        let mut buckets: HashMap<usize, (u64, [u64; 3])> = HashMap::new();
+        for pixel in sample.pixels() {
+            let [r, g, b] = pixel.0;
+            sum[0] += r as u64;
+            sum[1] += g as u64;
+            sum[2] += b as u64;
+            let key = ((r >> 5) as usize) << 6 | ((g >> 5) as usize) << 3 | (b >> 5) as usize;
+            let bucket = buckets.entry(key).or_default();
+            bucket.0 += 1;
+            bucket.1[0] += r as u64;
+            bucket.1[1] += g as u64;
+            bucket.1[2] += b as u64;
+        }
+
+        let average = sum.map(|channel| (channel / pixel_count) as u8);
+        let mut buckets: Vec<(u64, [u64; 3])> = buckets.into_values().collect();
+        buckets.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+        let dominant = buckets
+            .iter()
+            .take(DOMINANT_COUNT)
+            .map(|(count, sums)| sums.map(|channel| (channel / count) as u8))
+            .collect();
+
+        Self {
+            average,
+            luminance: luminance(average),
+            dominant,
+        }
+    }
+
+    /// The hue of the average color in degrees
+    pub fn hue(&self) -> f32 {
+        let [r, g, b] = self.average.map(|channel| channel as f32 / 255.0);
+        let max = r.max(g).max(b);
+        let min = r.min(g).min(b);
+        let delta = max - min;
+        if delta == 0.0 {
+            return 0.0;
+        }
+        let hue = if max == r {
+            ((g - b) / delta).rem_euclid(6.0)
+        } else if max == g {
+            (b - r) / delta + 2.0
+        } else {
+            (r - g) / delta + 4.0
+        };
+        hue * 60.0
+    }
+
+    /// How different two splashes look next to each other, smaller is closer
+    pub fn distance(&self, other: &SplashColors) -> f32 {
+        let color: f32 = self
+            .average
+            .iter()
+            .zip(other.average.iter())
+            .map(|(a, b)| (*a as f32 - *b as f32).powi(2))
+            .sum::<f32>()
+            .sqrt()
+            / 255.0;
+        color + (self.luminance - other.luminance).abs()
+    }
+}
+
+fn luminance(color: [u8; 3]) -> f32 {
+    let [r, g, b] = color.map(|channel| channel as f32 / 255.0);
+    0.2126 * r + 0.7152 * g + 0.0722 * b
+}
+
+/// How the cells of a tile are ordered before they're placed on the canvas
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Arrangement {
+    /// Keep the order the splashes were given in
+    #[default]
+    None,
+    /// Dark to light
+    Luminance,
+    /// Around the color wheel
+    Hue,
+    /// Each splash is followed by the remaining splash closest in color
+    Harmonize,
+}
+
+/// Order `ids` according to `arrangement`
+///
+/// Splashes without an entry in `colors` keep their relative order and are placed last.
+pub fn arrange(
+    ids: &[u64],
+    colors: &HashMap<u64, SplashColors>,
+    arrangement: Arrangement,
+) -> Vec<u64> {
+    let (mut analyzed, missing): (Vec<u64>, Vec<u64>) =
+        ids.iter().partition(|id| colors.contains_key(id));
+    match arrangement {
+        Arrangement::None => return ids.to_vec(),
+        Arrangement::Luminance => {
+            analyzed.sort_by(|a, b| colors[a].luminance.total_cmp(&colors[b].luminance))
+        }
+        Arrangement::Hue => analyzed.sort_by(|a, b| {
+            colors[a]
+                .hue()
+                .total_cmp(&colors[b].hue())
+                .then(colors[a].luminance.total_cmp(&colors[b].luminance))
+        }),
+        Arrangement::Harmonize => analyzed = harmonize(analyzed, colors),
+    }
+    analyzed.extend(missing);
+    analyzed
+}
+
+/// Greedily chain splashes together, starting from the darkest one
+fn harmonize(mut remaining: Vec<u64>, colors: &HashMap<u64, SplashColors>) -> Vec<u64> {
+    let mut ordered = Vec::with_capacity(remaining.len());
+    let start = remaining
+        .iter()
+        .enumerate()
+        .min_by(|a, b| colors[a.1].luminance.total_cmp(&colors[b.1].luminance))
+        .map(|(index, _)| index);
+    let Some(start) = start else {
+        return ordered;
+    };
+    ordered.push(remaining.swap_remove(start));
+    while !remaining.is_empty() {
+        let last = &colors[ordered.last().unwrap()];
+        let next = remaining
+            .iter()
+            .enumerate()
+            .min_by(|a, b| {
+                last.distance(&colors[a.1])
+                    .total_cmp(&last.distance(&colors[b.1]))
+            })
+            .map(|(index, _)| index)
+            .unwrap();
+        ordered.push(remaining.swap_remove(next));
+    }
+    ordered
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use image::{DynamicImage, Rgb, RgbImage};
+
+    use super::{arrange, Arrangement, SplashColors};
+
+    fn solid(color: [u8; 3]) -> SplashColors {
+        SplashColors::from_image(&DynamicImage::ImageRgb8(RgbImage::from_pixel(
+            32,
+            18,
+            Rgb(color),
+        )))
+    }
+
+    #[test]
+    fn solid_color() {
+        let colors = solid([200, 40, 40]);
+        assert_eq!(colors.average, [200, 40, 40]);
+        assert_eq!(colors.dominant, vec![[200, 40, 40]]);
+        assert!(solid([0, 0, 0]).luminance.abs() < 1e-6);
+        assert!((solid([255, 255, 255]).luminance - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn arrange_by_luminance() {
+        let colors = HashMap::from([
+            (1, solid([255, 255, 255])),
+            (2, solid([0, 0, 0])),
+            (3, solid([128, 128, 128])),
+        ]);
+        assert_eq!(
+            arrange(&[1, 2, 3, 4], &colors, Arrangement::Luminance),
+            vec![2, 3, 1, 4]
+        );
+        assert_eq!(
+            arrange(&[1, 2, 3, 4], &colors, Arrangement::None),
+            vec![1, 2, 3, 4]
+        );
+    }
+
+    #[test]
+    fn harmonize_neighbours() {
+        let colors = HashMap::from([
+            (1, solid([10, 10, 10])),
+            (2, solid([250, 0, 0])),
+            (3, solid([20, 20, 20])),
+            (4, solid([240, 10, 10])),
+        ]);
+        assert_eq!(
+            arrange(&[2, 1, 4, 3], &colors, Arrangement::Harmonize),
+            vec![1, 3, 4, 2]
+        );
+    }
+}
@@ -0,0 +1,234 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+use anyhow::{anyhow, Context};
+
+/// Environment variable that puts everything rusty-splash stores under a single directory
+pub const DATA_DIR_VAR: &str = "RUSTY_SPLASH_DATA_DIR";
+
+const APP_DIR: &str = "rusty-splash";
+
+static PATHS: OnceLock<Paths> = OnceLock::new();
+
+/// Which of the directories in [`Paths`] a file belongs in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Location {
+    /// Settings the user edits
+    Config,
+    /// Files that can be thrown away and fetched again
+    Cache,
+    /// Everything else, app state, splashes and tiles
+    Data,
+}
+
+/// The directories rusty-splash reads and writes
+///
+/// Resolved in order from `--data-dir`, [`DATA_DIR_VAR`], an existing `~/rusty-splash` from
+/// before the XDG layout, and finally `XDG_CONFIG_HOME`, `XDG_CACHE_HOME` and `XDG_DATA_HOME`
+/// (or the platform's equivalents). The first two and `~/rusty-splash` keep everything in
+/// one directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Paths {
+    pub config: PathBuf,
+    pub cache: PathBuf,
+    pub data: PathBuf,
+}
+
+impl Paths {
+    /// Keep config, cache and data together in `root`
+    pub fn single(root: impl Into<PathBuf>) -> Self {
+        let root = root.into();
+        Self {
+            config: root.clone(),
+            cache: root.clone(),
+            data: root,
+        }
+    }
+
+    pub fn resolve(data_dir: Option<PathBuf>) -> anyhow::Result<Self> {
+        if let Some(root) = data_dir {
+            return Ok(Self::single(root));
+        }
+        if let Some(root) = env::var_os(DATA_DIR_VAR).filter(|root| !root.is_empty()) {
+            return Ok(Self::single(root));
+        }
+        let home = dirs::home_dir().ok_or(anyhow!("couldn't get home dir"))?;
+        let legacy = home.join(APP_DIR);
+        let paths = Self {
+            config: xdg_dir("XDG_CONFIG_HOME", dirs::config_dir())?.join(APP_DIR),
+            cache: xdg_dir("XDG_CACHE_HOME", dirs::cache_dir())?.join(APP_DIR),
+            data: xdg_dir("XDG_DATA_HOME", dirs::data_dir())?.join(APP_DIR),
+        };
+        if legacy.join("app.json").exists() && !paths.data.exists() {
+            return Ok(Self::single(legacy));
+        }
+        Ok(paths)
+    }
+
+    pub fn dir(&self, location: Location) -> &Path {
+        match location {
+            Location::Config => &self.config,
+            Location::Cache => &self.cache,
+            Location::Data => &self.data,
+        }
+    }
+
+    pub fn downloads(&self) -> PathBuf {
+        self.data.join("downloads")
+    }
+
+    pub fn tiles(&self) -> PathBuf {
+        self.data.join("tiles")
+    }
+
+    /// Where resized splashes are kept between builds
+    pub fn cells(&self) -> PathBuf {
+        self.cache.join("cells")
+    }
+
+    /// Where splashes replaced by `sync` are kept
+    pub fn archive(&self) -> PathBuf {
+        self.data.join("archive")
+    }
+
+    /// The directory for `location`, created if it's missing
+    pub fn ensure(&self, location: Location) -> anyhow::Result<&Path> {
+        let dir = self.dir(location);
+        if !dir.exists() {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("failed to create dir path: {:?}", dir))?;
+        }
+        Ok(dir)
+    }
+}
+
+/// `name` made safe to use as a single file or directory name
+///
+/// Tile names and queries can hold anything, so separators and characters some filesystems
+/// reject are replaced, as are leading dots, which would hide the file or climb out with `..`.
+pub fn file_name(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| match c.is_control() || r#"/\:*?"<>|"#.contains(c) {
+            true => '_',
+            false => c,
+        })
+        .collect();
+    let dots = name.len() - name.trim_start_matches('.').len();
+    match "_".repeat(dots) + &name[dots..] {
+        name if name.is_empty() => String::from("_"),
+        name => name,
+    }
+}
+
+/// An absolute `XDG_*_HOME` if it's set, the platform default otherwise
+fn xdg_dir(var: &str, default: Option<PathBuf>) -> anyhow::Result<PathBuf> {
+    env::var_os(var)
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .or(default)
+        .ok_or(anyhow!("couldn't find a directory for {var}"))
+}
+
+/// Set the directories used for the rest of the process
+///
+/// Has to be called before anything is loaded, fails if the paths were already in use.
+pub fn init(data_dir: Option<PathBuf>) -> anyhow::Result<&'static Paths> {
+    let paths = Paths::resolve(data_dir)?;
+    PATHS
+        .set(paths)
+        .map_err(|_| anyhow!("the data directories were already set"))?;
+    Ok(PATHS.get().unwrap())
+}
+
+/// A test thread's directory, removed with everything in it when the thread's test is done
+#[cfg(test)]
+struct TestDir(&'static Paths);
+
+#[cfg(test)]
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0.data);
+    }
+}
+
+#[cfg(test)]
+thread_local! {
+    /// Each test runs on its own thread, so giving every thread its own directory keeps tests
+    /// from seeing each other's files
+    static TEST_PATHS: TestDir = {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let dir = env::temp_dir().join(format!(
+            "rusty-splash-test-{}-{}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        TestDir(Box::leak(Box::new(Paths::single(dir))))
+    };
+}
+
+/// The directories in use, resolved from the environment if [`init`] wasn't called
+///
+/// Tests always get a temporary directory so they never touch the user's files.
+pub fn get() -> anyhow::Result<&'static Paths> {
+    #[cfg(test)]
+    return Ok(TEST_PATHS.with(|dir| dir.0));
+    #[cfg(not(test))]
+    {
+        if let Some(paths) = PATHS.get() {
+            return Ok(paths);
+        }
+        // another thread may have got there first, either way the stored paths win
+        let _ = PATHS.set(Paths::resolve(None)?);
+        Ok(PATHS.get().unwrap())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env, fs, path::PathBuf, thread};
+
+    use super::{file_name, get, Location, Paths};
+
+    #[test]
+    fn data_dir_wins() {
+        let paths = Paths::resolve(Some(PathBuf::from("/srv/splashes"))).unwrap();
+        assert_eq!(paths, Paths::single("/srv/splashes"));
+        assert_eq!(paths.tiles(), PathBuf::from("/srv/splashes/tiles"));
+        assert_eq!(paths.dir(Location::Cache), paths.dir(Location::Config));
+    }
+
+    #[test]
+    fn names_stay_in_their_dir() {
+        assert_eq!(file_name("star guardian"), "star guardian");
+        assert_eq!(file_name("../../etc/passwd"), "___.._etc_passwd");
+        assert_eq!(file_name(".."), "__");
+        assert_eq!(file_name(""), "_");
+        assert_eq!(file_name("pool party: 2/3"), "pool party_ 2_3");
+        let tiles = PathBuf::from("/srv/splashes/tiles");
+        for name in ["..", "../x", "a/../../b", "/abs", "."] {
+            assert_eq!(tiles.join(file_name(name)).parent(), Some(tiles.as_path()));
+        }
+    }
+
+    #[test]
+    fn tests_use_temp_dir() {
+        assert!(get().unwrap().data.starts_with(env::temp_dir()));
+    }
+
+    #[test]
+    fn test_dirs_are_removed() {
+        let dir = thread::spawn(|| {
+            let data = get().unwrap().ensure(Location::Data).unwrap().to_path_buf();
+            fs::write(data.join("app.json"), "{}").unwrap();
+            data
+        })
+        .join()
+        .unwrap();
+        assert!(!dir.exists());
+    }
+}
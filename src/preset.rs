@@ -0,0 +1,214 @@
+use std::{fs, path::Path};
+
+use anyhow::{anyhow, Context};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    app::{TileInstance, TileLayout, TileSource},
+    cdragon::{CDragon, SkinQuery},
+    palette::Arrangement,
+};
+
+/// The version of the preset file format
+pub const PRESET_VERSION: u32 = 1;
+
+/// A tile written to a standalone file so it can be shared
+///
+/// Saved as TOML when the file ends in `.toml` and as JSON otherwise.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Preset {
+    pub version: u32,
+    pub name: String,
+    #[serde(default)]
+    pub arrangement: Arrangement,
+    #[serde(default)]
+    pub layout: TileLayout,
+    /// Set for smart tiles, whose skins are re-evaluated from the query on import
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub query: Option<SkinQuery>,
+    /// The tile's skins in order
+    pub skins: Vec<PresetSkin>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PresetSkin {
+    pub id: u64,
+    /// Only there to make the file readable, the id is what's imported
+    #[serde(default)]
+    pub name: String,
+}
+
+enum Format {
+    Json,
+    Toml,
+}
+
+impl Format {
+    fn of(path: &Path) -> Format {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some(extension) if extension.eq_ignore_ascii_case("toml") => Format::Toml,
+            _ => Format::Json,
+        }
+    }
+}
+
+impl Preset {
+    pub fn from_tile(tile: &TileInstance, cdragon: &CDragon) -> Self {
+        Self {
+            version: PRESET_VERSION,
+            name: tile.name.clone(),
+            arrangement: tile.arrangement,
+            layout: tile.layout,
+            query: match &tile.source {
+                TileSource::Static => None,
+                TileSource::Query(query) => Some(query.clone()),
+            },
+            skins: tile
+                .splash_ids
+                .iter()
+                .map(|id| PresetSkin {
+                    id: *id,
+                    name: cdragon
+                        .skin(*id)
+                        .map(|skin| skin.name.clone())
+                        .unwrap_or_default(),
+                })
+                .collect(),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let contents = match Format::of(path) {
+            Format::Json => serde_json::to_string_pretty(self)?,
+            Format::Toml => toml::to_string_pretty(self)?,
+        };
+        fs::write(path, contents).with_context(|| format!("failed to write preset {:?}", path))
+    }
+
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("failed to read preset {:?}", path))?;
+        let preset: Preset = match Format::of(path) {
+            Format::Json => serde_json::from_str(&contents)
+                .with_context(|| format!("{:?} isn't a valid preset", path))?,
+            Format::Toml => toml::from_str(&contents)
+                .with_context(|| format!("{:?} isn't a valid preset", path))?,
+        };
+        if preset.version > PRESET_VERSION {
+            return Err(anyhow!(
+                "{:?} is a version {} preset, this version of rusty-splash only reads up to version {}",
+                path,
+                preset.version,
+                PRESET_VERSION
+            ));
+        }
+        Ok(preset)
+    }
+
+    /// Turn the preset into a new tile, skipping skins that aren't in `cdragon`
+    ///
+    /// Returns the tile and the skins that couldn't be found.
+    pub fn into_tile(self, cdragon: &CDragon) -> anyhow::Result<(TileInstance, Vec<PresetSkin>)> {
+        let (found, missing): (Vec<PresetSkin>, Vec<PresetSkin>) = self
+            .skins
+            .into_iter()
+            .partition(|skin| cdragon.skin(skin.id).is_some());
+        let mut tile = TileInstance::new(self.name);
+        tile.arrangement = self.arrangement;
+        tile.layout = self.layout;
+        if let Some(query) = self.query {
+            tile.source = TileSource::Query(query);
+        }
+        tile.add_splashes(&found.iter().map(|skin| skin.id).collect::<Vec<u64>>());
+        tile.refresh(cdragon)?;
+        Ok((tile, missing))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use crate::{
+        app::TileInstance,
+        cdragon::{fixtures, SkinQuery},
+        palette::Arrangement,
+        paths::{self, Location},
+    };
+
+    use super::{Preset, PRESET_VERSION};
+
+    fn tile() -> TileInstance {
+        let mut tile = TileInstance::new("Star Guardians");
+        tile.arrangement = Arrangement::Hue;
+        tile.layout.min_cell = (640, 360);
+        tile.add_splashes(&[222020, 103015, 999999]);
+        tile
+    }
+
+    #[test]
+    fn round_trip() {
+        let cdragon = fixtures::cdragon();
+        let preset = Preset::from_tile(&tile(), &cdragon);
+        assert_eq!(preset.version, PRESET_VERSION);
+        assert_eq!(preset.skins[0].name, "Star Guardian Jinx");
+        assert_eq!(preset.skins[2].name, "");
+
+        let dir = paths::get().unwrap().ensure(Location::Data).unwrap();
+        for file in ["preset.json", "preset.toml"] {
+            let path = dir.join(file);
+            preset.save(&path).unwrap();
+            assert_eq!(Preset::load(&path).unwrap(), preset);
+        }
+    }
+
+    #[test]
+    fn import_reports_missing_skins() {
+        let cdragon = fixtures::cdragon();
+        let original = tile();
+        let (imported, missing) = Preset::from_tile(&original, &cdragon)
+            .into_tile(&cdragon)
+            .unwrap();
+        assert_ne!(imported.id, original.id);
+        assert_eq!(imported.splash_ids, vec![222020, 103015]);
+        assert_eq!(imported.arrangement, Arrangement::Hue);
+        assert_eq!(imported.layout, original.layout);
+        assert_eq!(imported.source, original.source);
+        assert_eq!(missing.len(), 1);
+        assert_eq!(missing[0].id, 999999);
+    }
+
+    #[test]
+    fn smart_tiles_are_refreshed_on_import() {
+        let cdragon = fixtures::cdragon();
+        let mut original = TileInstance::with_query(
+            "Star Guardians",
+            SkinQuery {
+                skin_line: Some(String::from("Star Guardian")),
+                ..SkinQuery::default()
+            },
+        );
+        original.add_splashes(&[222020, 999999]);
+        let mut preset = Preset::from_tile(&original, &cdragon);
+        assert_eq!(
+            preset.query,
+            Some(SkinQuery {
+                skin_line: Some(String::from("Star Guardian")),
+                ..SkinQuery::default()
+            })
+        );
+        preset.skins.pop();
+        let (imported, missing) = preset.into_tile(&cdragon).unwrap();
+        assert_eq!(imported.source, original.source);
+        assert!(missing.is_empty());
+        assert!(imported.splash_ids.contains(&103015));
+    }
+
+    #[test]
+    fn newer_version() {
+        let dir = paths::get().unwrap().ensure(Location::Data).unwrap();
+        let path = dir.join("preset.json");
+        fs::write(&path, r#"{"version": 99, "name": "future", "skins": []}"#).unwrap();
+        assert!(Preset::load(&path).is_err());
+    }
+}
@@ -0,0 +1,315 @@
+use std::{
+    env,
+    fmt::Write,
+    io::Cursor,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Context};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use clap::ValueEnum;
+use image::{imageops::FilterType, DynamicImage, GenericImage, ImageFormat, Rgb, RgbImage};
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use serde::{Deserialize, Serialize};
+
+/// The size of a terminal cell in pixels assumed when sizing kitty and sixel images
+const CELL: (u32, u32) = (10, 20);
+
+/// How many bytes of base64 go in each kitty graphics escape
+const KITTY_CHUNK: usize = 4096;
+
+/// The size of each thumbnail on a contact sheet, close to a splash's aspect ratio
+pub const THUMBNAIL: (u32, u32) = (320, 189);
+
+/// The gap between thumbnails on a contact sheet
+const GAP: u32 = 4;
+
+/// How images are drawn in the terminal
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Protocol {
+    /// The kitty graphics protocol, also supported by WezTerm, Ghostty and Konsole
+    Kitty,
+    Sixel,
+    /// Coloured half blocks, works in any terminal with true colour
+    Blocks,
+}
+
+/// The parts of the environment used to pick a protocol
+#[derive(Debug, Clone, Default)]
+pub struct Terminal {
+    /// `TERM`, lowercased
+    pub term: String,
+    /// `TERM_PROGRAM`, lowercased
+    pub program: String,
+    pub kitty: bool,
+    pub columns: u32,
+    pub rows: u32,
+}
+
+impl Terminal {
+    pub fn from_env() -> Self {
+        let (rows, columns) = console::Term::stdout().size();
+        Self {
+            term: env::var("TERM").unwrap_or_default().to_lowercase(),
+            program: env::var("TERM_PROGRAM").unwrap_or_default().to_lowercase(),
+            kitty: env::var_os("KITTY_WINDOW_ID").is_some(),
+            columns: columns.into(),
+            rows: rows.into(),
+        }
+    }
+}
+
+impl Protocol {
+    /// Pick the best protocol the terminal is known to support
+    pub fn detect(terminal: &Terminal) -> Protocol {
+        let kitty_programs = ["wezterm", "ghostty", "konsole"];
+        let sixel_terms = ["foot", "mlterm", "yaft", "contour"];
+        if terminal.kitty
+            || terminal.term.contains("kitty")
+            || kitty_programs.contains(&terminal.program.as_str())
+        {
+            Protocol::Kitty
+        } else if terminal.term.contains("sixel")
+            || sixel_terms
+                .iter()
+                .any(|term| terminal.term.starts_with(term))
+        {
+            Protocol::Sixel
+        } else {
+            Protocol::Blocks
+        }
+    }
+
+    /// Draw `image` as escape sequences that fit in `columns` by `rows` cells
+    pub fn render(self, image: &DynamicImage, columns: u32, rows: u32) -> anyhow::Result<String> {
+        let (columns, rows) = (columns.max(1), rows.max(1));
+        match self {
+            Protocol::Kitty => {
+                let image = image.resize(columns * CELL.0, rows * CELL.1, FilterType::Triangle);
+                kitty(&image, image.width().div_ceil(CELL.0))
+            }
+            Protocol::Sixel => {
+                let image = image.resize(columns * CELL.0, rows * CELL.1, FilterType::Triangle);
+                Ok(sixel(&image.to_rgb8()))
+            }
+            Protocol::Blocks => {
+                let image = image.resize(columns, rows * 2, FilterType::Triangle);
+                Ok(blocks(&image.to_rgb8()))
+            }
+        }
+    }
+}
+
+/// Transmit and show a PNG in one go, `columns` wide
+fn kitty(image: &DynamicImage, columns: u32) -> anyhow::Result<String> {
+    let mut png = Vec::new();
+    image.write_to(&mut Cursor::new(&mut png), ImageFormat::Png)?;
+    let encoded = STANDARD.encode(png);
+    let chunks: Vec<&[u8]> = encoded.as_bytes().chunks(KITTY_CHUNK).collect();
+    let mut out = String::new();
+    for (index, chunk) in chunks.iter().enumerate() {
+        let more = u8::from(index + 1 < chunks.len());
+        let chunk = std::str::from_utf8(chunk)?;
+        match index {
+            0 => write!(out, "\x1b_Ga=T,f=100,c={columns},m={more};{chunk}\x1b\\")?,
+            _ => write!(out, "\x1b_Gm={more};{chunk}\x1b\\")?,
+        }
+    }
+    out.push('\n');
+    Ok(out)
+}
+
+/// Encode `image` as sixels using a 6x6x6 colour cube
+fn sixel(image: &RgbImage) -> String {
+    let level = |channel: u8| (u32::from(channel) * 5 + 127) / 255;
+    let index =
+        |pixel: &Rgb<u8>| (level(pixel[0]) * 36 + level(pixel[1]) * 6 + level(pixel[2])) as usize;
+    let (width, height) = image.dimensions();
+    let mut out = format!("\x1bPq\"1;1;{width};{height}");
+    for color in 0..216 {
+        let percent = |level: u32| level * 100 / 5;
+        let _ = write!(
+            out,
+            "#{color};2;{};{};{}",
+            percent(color / 36),
+            percent(color / 6 % 6),
+            percent(color % 6)
+        );
+    }
+    for band in (0..height).step_by(6) {
+        let band_height = (height - band).min(6);
+        let indices: Vec<Vec<usize>> = (0..band_height)
+            .map(|dy| {
+                (0..width)
+                    .map(|x| index(image.get_pixel(x, band + dy)))
+                    .collect()
+            })
+            .collect();
+        let mut used: Vec<usize> = indices.iter().flatten().copied().collect();
+        used.sort_unstable();
+        used.dedup();
+        for color in used {
+            let _ = write!(out, "#{color}");
+            let sixels = (0..width as usize).map(|x| {
+                let bits = (0..band_height as usize)
+                    .filter(|dy| indices[*dy][x] == color)
+                    .fold(0u8, |bits, dy| bits | 1 << dy);
+                char::from(63 + bits)
+            });
+            push_runs(&mut out, sixels);
+            out.push('$');
+        }
+        out.push('-');
+    }
+    out.push_str("\x1b\\");
+    out
+}
+
+/// Write sixel characters, run length encoding repeats
+fn push_runs(out: &mut String, sixels: impl Iterator<Item = char>) {
+    let mut run: Option<(char, usize)> = None;
+    let flush = |out: &mut String, run: Option<(char, usize)>| match run {
+        Some((sixel, count)) if count > 3 => {
+            let _ = write!(out, "!{count}{sixel}");
+        }
+        Some((sixel, count)) => out.extend(std::iter::repeat_n(sixel, count)),
+        None => {}
+    };
+    for sixel in sixels {
+        run = match run {
+            Some((current, count)) if current == sixel => Some((current, count + 1)),
+            previous => {
+                flush(out, previous);
+                Some((sixel, 1))
+            }
+        };
+    }
+    flush(out, run);
+}
+
+/// Draw two pixels per cell with upper half blocks
+fn blocks(image: &RgbImage) -> String {
+    let (width, height) = image.dimensions();
+    let mut out = String::new();
+    for y in (0..height).step_by(2) {
+        for x in 0..width {
+            let top = image.get_pixel(x, y);
+            let bottom = match y + 1 < height {
+                true => *image.get_pixel(x, y + 1),
+                false => Rgb([0, 0, 0]),
+            };
+            let _ = write!(
+                out,
+                "\x1b[38;2;{};{};{}m\x1b[48;2;{};{};{}m\u{2580}",
+                top[0], top[1], top[2], bottom[0], bottom[1], bottom[2]
+            );
+        }
+        out.push_str("\x1b[0m\n");
+    }
+    out
+}
+
+/// Lay thumbnails of `paths` out in a grid, row by row
+///
+/// # Arguments
+/// * `thumbnail` - the size each image is cropped to
+pub fn contact_sheet(paths: &[PathBuf], thumbnail: (u32, u32)) -> anyhow::Result<RgbImage> {
+    if paths.is_empty() {
+        return Err(anyhow!("there's nothing to put on the contact sheet"));
+    }
+    let columns = (paths.len() as f64).sqrt().ceil().clamp(1.0, 6.0) as u32;
+    let rows = (paths.len() as u32).div_ceil(columns);
+    let thumbnails = paths
+        .par_iter()
+        .map(|path| {
+            let image = image::open(path).with_context(|| format!("failed to open {:?}", path))?;
+            Ok(image
+                .resize_to_fill(thumbnail.0, thumbnail.1, FilterType::Triangle)
+                .to_rgb8())
+        })
+        .collect::<anyhow::Result<Vec<RgbImage>>>()?;
+    let mut sheet = RgbImage::new(
+        columns * (thumbnail.0 + GAP) - GAP,
+        rows * (thumbnail.1 + GAP) - GAP,
+    );
+    for (index, thumb) in thumbnails.iter().enumerate() {
+        let (column, row) = (index as u32 % columns, index as u32 / columns);
+        sheet.copy_from(
+            thumb,
+            column * (thumbnail.0 + GAP),
+            row * (thumbnail.1 + GAP),
+        )?;
+    }
+    Ok(sheet)
+}
+
+/// Draw the image at `path` in the terminal
+pub fn show(path: &Path, protocol: Protocol, columns: u32, rows: u32) -> anyhow::Result<String> {
+    let image = image::open(path).with_context(|| format!("failed to open {:?}", path))?;
+    protocol.render(&image, columns, rows)
+}
+
+#[cfg(test)]
+mod test {
+    use image::{DynamicImage, Rgb, RgbImage};
+
+    use crate::cdragon::fixtures;
+
+    use super::{blocks, contact_sheet, sixel, Protocol, Terminal};
+
+    #[test]
+    fn detect_protocol() {
+        let terminal = |term: &str, program: &str| Terminal {
+            term: term.to_string(),
+            program: program.to_string(),
+            ..Terminal::default()
+        };
+        assert_eq!(
+            Protocol::detect(&terminal("xterm-kitty", "")),
+            Protocol::Kitty
+        );
+        assert_eq!(
+            Protocol::detect(&terminal("xterm-256color", "wezterm")),
+            Protocol::Kitty
+        );
+        assert_eq!(Protocol::detect(&terminal("foot", "")), Protocol::Sixel);
+        assert_eq!(
+            Protocol::detect(&terminal("xterm-256color", "")),
+            Protocol::Blocks
+        );
+    }
+
+    #[test]
+    fn encode_small_images() {
+        let mut image = RgbImage::from_pixel(8, 7, Rgb([255, 0, 0]));
+        image.put_pixel(0, 6, Rgb([0, 0, 255]));
+
+        let encoded = sixel(&image);
+        assert!(encoded.starts_with("\x1bPq\"1;1;8;7"));
+        assert!(encoded.ends_with("-\x1b\\"));
+        // red fills the whole first band, so it's a single run
+        assert!(encoded.contains("#180!8~$"));
+        assert_eq!(encoded.matches('-').count(), 2);
+
+        let drawn = blocks(&image);
+        assert_eq!(drawn.lines().count(), 4);
+        assert_eq!(drawn.lines().next().unwrap().matches('\u{2580}').count(), 8);
+
+        let kitty = Protocol::Kitty
+            .render(&DynamicImage::ImageRgb8(image), 4, 2)
+            .unwrap();
+        assert!(kitty.starts_with("\x1b_Ga=T,f=100,c=4,m=0;"));
+    }
+
+    #[test]
+    fn sheet_grid() {
+        let paths: Vec<_> = (0..5)
+            .map(|index| fixtures::splash(&format!("{index}.png"), (30, 20), [index * 40, 0, 0]))
+            .collect();
+        let sheet = contact_sheet(&paths, (16, 9)).unwrap();
+        assert_eq!(sheet.dimensions(), (3 * 16 + 2 * 4, 2 * 9 + 4));
+        assert_eq!(sheet.get_pixel(0, 0), &Rgb([0, 0, 0]));
+        assert_eq!(sheet.get_pixel(20, 0), &Rgb([40, 0, 0]));
+    }
+}
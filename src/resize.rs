@@ -0,0 +1,387 @@
+use std::{
+    f32::consts::PI,
+    fs::File,
+    io::{BufReader, Read},
+    path::Path,
+};
+
+use anyhow::{anyhow, Context};
+use clap::ValueEnum;
+use image::{
+    imageops::FilterType, DynamicImage, GenericImageView, GrayImage, ImageFormat, Rgb, RgbImage,
+};
+use jpeg_decoder::{Decoder, PixelFormat};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use wide::f32x4;
+
+/// The resampling filter cells are resized with, fastest first
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum ResizeFilter {
+    Nearest,
+    /// Bilinear
+    Triangle,
+    CatmullRom,
+    Gaussian,
+    /// The sharpest and slowest
+    #[default]
+    Lanczos3,
+}
+
+impl From<ResizeFilter> for FilterType {
+    fn from(filter: ResizeFilter) -> Self {
+        match filter {
+            ResizeFilter::Nearest => FilterType::Nearest,
+            ResizeFilter::Triangle => FilterType::Triangle,
+            ResizeFilter::CatmullRom => FilterType::CatmullRom,
+            ResizeFilter::Gaussian => FilterType::Gaussian,
+            ResizeFilter::Lanczos3 => FilterType::Lanczos3,
+        }
+    }
+}
+
+impl ResizeFilter {
+    /// How far the filter reaches to either side of a pixel when it isn't shrinking
+    fn support(self) -> f32 {
+        match self {
+            ResizeFilter::Nearest => 0.5,
+            ResizeFilter::Triangle => 1.0,
+            ResizeFilter::CatmullRom => 2.0,
+            ResizeFilter::Gaussian | ResizeFilter::Lanczos3 => 3.0,
+        }
+    }
+
+    /// The weight of a pixel `x` pixels away, left unnormalized
+    fn kernel(self, x: f32) -> f32 {
+        let sinc = |x: f32| match x == 0.0 {
+            true => 1.0,
+            false => (PI * x).sin() / (PI * x),
+        };
+        let a = x.abs();
+        match self {
+            ResizeFilter::Nearest => f32::from(u8::from((-0.5..0.5).contains(&x))),
+            ResizeFilter::Triangle => (1.0 - a).max(0.0),
+            ResizeFilter::CatmullRom if a < 1.0 => 1.5 * a.powi(3) - 2.5 * a.powi(2) + 1.0,
+            ResizeFilter::CatmullRom if a < 2.0 => {
+                -0.5 * a.powi(3) + 2.5 * a.powi(2) - 4.0 * a + 2.0
+            }
+            ResizeFilter::CatmullRom => 0.0,
+            // the same sigma of 0.5 the image crate uses
+            ResizeFilter::Gaussian => (-2.0 * x * x).exp(),
+            ResizeFilter::Lanczos3 if a < 3.0 => sinc(x) * sinc(x / 3.0),
+            ResizeFilter::Lanczos3 => 0.0,
+        }
+    }
+}
+
+/// What does the resizing
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum ResizeBackend {
+    /// The image crate's resize
+    #[default]
+    Image,
+    /// A separable convolution that filters a pixel's channels together on SIMD lanes, see
+    /// [`resize_simd`]
+    Simd,
+}
+
+/// How cells are resized
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ResizeOptions {
+    pub filter: ResizeFilter,
+    /// Whether JPEGs are shrunk while they're decoded, see [`open_scaled`]
+    pub fast_decode: Option<bool>,
+    pub backend: ResizeBackend,
+}
+
+/// Open the image at `path`, shrunk to no less than `size` while it's decoded when it's a JPEG
+///
+/// JPEGs can be shrunk by up to 8 times while they're decoded, which skips most of the work of
+/// decoding and resizing a full size splash. `fast` turns that on or off, and when it's `None`
+/// JPEGs are only shrunk when they're at least twice `size`, where it saves the most. Anything
+/// else is opened at full size.
+pub fn open_scaled(
+    path: &Path,
+    size: (u32, u32),
+    fast: Option<bool>,
+) -> anyhow::Result<DynamicImage> {
+    let is_jpeg = path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("jpg"));
+    if fast != Some(false) && is_jpeg {
+        let file = File::open(path).with_context(|| format!("failed to open {:?}", path))?;
+        let scaled = decode_scaled(BufReader::new(file), size, fast)
+            .with_context(|| format!("failed to decode {:?}", path))?;
+        if let Some(image) = scaled {
+            return Ok(image);
+        }
+    }
+    image::open(path).with_context(|| format!("failed to open {:?}", path))
+}
+
+/// [`open_scaled`] for an image already in memory
+pub fn load_scaled(
+    bytes: &[u8],
+    size: (u32, u32),
+    fast: Option<bool>,
+) -> anyhow::Result<DynamicImage> {
+    let is_jpeg = image::guess_format(bytes).is_ok_and(|format| format == ImageFormat::Jpeg);
+    if fast != Some(false) && is_jpeg {
+        if let Some(image) = decode_scaled(bytes, size, fast)? {
+            return Ok(image);
+        }
+    }
+    image::load_from_memory(bytes).with_context(|| "failed to decode an image")
+}
+
+/// `None` for JPEGs that aren't worth shrinking unless `fast` says so, and for formats the image
+/// crate converts better, like CMYK
+fn decode_scaled(
+    reader: impl Read,
+    size: (u32, u32),
+    fast: Option<bool>,
+) -> anyhow::Result<Option<DynamicImage>> {
+    let mut decoder = Decoder::new(reader);
+    decoder.read_info()?;
+    let info = decoder
+        .info()
+        .ok_or(anyhow!("the image has no JPEG header"))?;
+    let halves = u32::from(info.width) >= size.0.saturating_mul(2)
+        && u32::from(info.height) >= size.1.saturating_mul(2);
+    if !fast.unwrap_or(halves) {
+        return Ok(None);
+    }
+    let requested = (
+        size.0.try_into().unwrap_or(u16::MAX),
+        size.1.try_into().unwrap_or(u16::MAX),
+    );
+    let (width, height) = decoder.scale(requested.0, requested.1)?;
+    let pixels = decoder.decode()?;
+    let (width, height) = (u32::from(width), u32::from(height));
+    let invalid = || anyhow!("the image decoded to the wrong size");
+    let format = decoder.info().map(|info| info.pixel_format);
+    Ok(match format {
+        Some(PixelFormat::RGB24) => Some(DynamicImage::ImageRgb8(
+            RgbImage::from_raw(width, height, pixels).ok_or_else(invalid)?,
+        )),
+        Some(PixelFormat::L8) => Some(DynamicImage::ImageLuma8(
+            GrayImage::from_raw(width, height, pixels).ok_or_else(invalid)?,
+        )),
+        _ => None,
+    })
+}
+
+/// The splash at `path` scaled to cover `size` and cropped by `crop`, centered
+pub fn resize_cell(
+    path: &Path,
+    size: (u32, u32),
+    crop: (u32, u32),
+    options: ResizeOptions,
+) -> anyhow::Result<DynamicImage> {
+    let image = open_scaled(path, size, options.fast_decode)?;
+    Ok(resize_image(&image, size, crop, options))
+}
+
+/// [`resize_cell`] for a decoded image
+///
+/// Splashes are scaled to fill `size` and cropped to it rather than fitted inside it, so a splash
+/// with a different shape than the rest still fills its whole cell.
+pub fn resize_image(
+    image: &DynamicImage,
+    size: (u32, u32),
+    crop: (u32, u32),
+    options: ResizeOptions,
+) -> DynamicImage {
+    let (x, y, width, height) = (crop.0 / 2, crop.1 / 2, size.0 - crop.0, size.1 - crop.1);
+    match options.backend {
+        ResizeBackend::Image => image
+            .resize_to_fill(size.0, size.1, options.filter.into())
+            .crop_imm(x, y, width, height),
+        ResizeBackend::Simd => {
+            let filled = fill(image.dimensions(), size);
+            let resized = resize_simd(&image.to_rgb8(), filled, options.filter);
+            DynamicImage::ImageRgb8(resized).crop_imm(
+                (filled.0 - size.0) / 2 + x,
+                (filled.1 - size.1) / 2 + y,
+                width,
+                height,
+            )
+        }
+    }
+}
+
+/// The smallest size with the shape of `source` that covers `size`
+fn fill(source: (u32, u32), size: (u32, u32)) -> (u32, u32) {
+    let scale = f64::max(
+        f64::from(size.0) / f64::from(source.0),
+        f64::from(size.1) / f64::from(source.1),
+    );
+    let scaled = |side: u32, min: u32| ((f64::from(side) * scale).round() as u32).max(min);
+    (scaled(source.0, size.0), scaled(source.1, size.1))
+}
+
+/// `image` resized to exactly `size`, filtering rows and then columns
+///
+/// Each pixel is one `f32x4` with its channels in the first three lanes, so every tap of the
+/// filter is a single multiply and add whichever instruction set the lanes map to.
+pub fn resize_simd(image: &RgbImage, size: (u32, u32), filter: ResizeFilter) -> RgbImage {
+    let (from_width, width) = (image.width() as usize, size.0 as usize);
+    let pixels: Vec<f32x4> = image
+        .pixels()
+        .map(|Rgb([r, g, b])| f32x4::from([f32::from(*r), f32::from(*g), f32::from(*b), 0.0]))
+        .collect();
+    let columns = taps(image.width(), size.0, filter);
+    let mut rows = vec![f32x4::ZERO; width * image.height() as usize];
+    rows.par_chunks_mut(width)
+        .zip(pixels.par_chunks(from_width))
+        .for_each(|(row, from)| {
+            for (pixel, (start, weights)) in row.iter_mut().zip(&columns) {
+                *pixel = weights
+                    .iter()
+                    .zip(&from[*start..])
+                    .fold(f32x4::ZERO, |sum, (weight, from)| {
+                        from.mul_add(f32x4::splat(*weight), sum)
+                    });
+            }
+        });
+    let mut resized = vec![0; width * size.1 as usize * 3];
+    resized
+        .par_chunks_mut(width * 3)
+        .zip(taps(image.height(), size.1, filter))
+        .for_each(|(row, (start, weights))| {
+            let mut sums = vec![f32x4::ZERO; width];
+            for (index, weight) in weights.iter().enumerate() {
+                let from = &rows[(start + index) * width..][..width];
+                for (sum, from) in sums.iter_mut().zip(from) {
+                    *sum = from.mul_add(f32x4::splat(*weight), *sum);
+                }
+            }
+            for (pixel, sum) in row.chunks_exact_mut(3).zip(sums) {
+                let [r, g, b, _] = sum
+                    .round()
+                    .max(f32x4::ZERO)
+                    .min(f32x4::splat(255.0))
+                    .to_array();
+                pixel.copy_from_slice(&[r as u8, g as u8, b as u8]);
+            }
+        });
+    RgbImage::from_raw(size.0, size.1, resized).expect("the buffer fits the size")
+}
+
+/// For each of `to` pixels along an axis, the first of `from` pixels it's made from and the
+/// weights of it and the ones after it
+fn taps(from: u32, to: u32, filter: ResizeFilter) -> Vec<(usize, Vec<f32>)> {
+    let scale = from as f32 / to as f32;
+    // shrinking widens the filter so every source pixel counts
+    let stretch = scale.max(1.0);
+    let support = filter.support() * stretch;
+    (0..to)
+        .map(|index| {
+            let center = (index as f32 + 0.5) * scale;
+            let start = (center - support).floor().max(0.0) as usize;
+            let end = ((center + support).ceil() as usize).clamp(start + 1, from as usize);
+            let mut weights: Vec<f32> = (start..end)
+                .map(|source| filter.kernel((source as f32 + 0.5 - center) / stretch))
+                .collect();
+            let total: f32 = weights.iter().sum();
+            match total == 0.0 {
+                // too narrow to reach any pixel's center, so take the closest one
+                true => weights[(center as usize).clamp(start, end - 1) - start] = 1.0,
+                false => weights.iter_mut().for_each(|weight| *weight /= total),
+            }
+            (start, weights)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use image::{DynamicImage, GenericImageView, Rgb, RgbImage};
+
+    use crate::cdragon::fixtures;
+
+    use super::{
+        load_scaled, open_scaled, resize_cell, resize_image, ResizeBackend, ResizeFilter,
+        ResizeOptions,
+    };
+
+    #[test]
+    fn jpegs_are_scaled_on_decode() {
+        let path = fixtures::splash("1000.jpg", (1216, 720), [200, 40, 40]);
+        let full = open_scaled(&path, (300, 170), Some(false)).unwrap();
+        assert_eq!(full.dimensions(), (1216, 720));
+        let scaled = open_scaled(&path, (300, 170), Some(true)).unwrap();
+        assert_eq!(scaled.dimensions(), (304, 180));
+        let bytes = std::fs::read(&path).unwrap();
+        assert_eq!(
+            load_scaled(&bytes, (300, 170), Some(true))
+                .unwrap()
+                .dimensions(),
+            (304, 180)
+        );
+        assert_eq!(
+            open_scaled(&path, (1000, 600), Some(true))
+                .unwrap()
+                .dimensions(),
+            (1216, 720)
+        );
+
+        // left alone, only splashes at least twice the cell are shrunk
+        assert_eq!(
+            open_scaled(&path, (300, 170), None).unwrap().dimensions(),
+            (304, 180)
+        );
+        assert_eq!(
+            load_scaled(&bytes, (300, 170), None).unwrap().dimensions(),
+            (304, 180)
+        );
+        assert_eq!(
+            open_scaled(&path, (700, 400), None).unwrap().dimensions(),
+            (1216, 720)
+        );
+
+        for filter in [ResizeFilter::Nearest, ResizeFilter::Lanczos3] {
+            let options = ResizeOptions {
+                filter,
+                fast_decode: Some(true),
+                ..ResizeOptions::default()
+            };
+            let cell = resize_cell(&path, (300, 170), (10, 0), options).unwrap();
+            assert_eq!(cell.dimensions(), (290, 170));
+        }
+    }
+
+    #[test]
+    fn simd_matches_the_image_crate() {
+        let image = DynamicImage::ImageRgb8(RgbImage::from_fn(400, 300, |x, y| {
+            Rgb([(x / 2) as u8, (y * 255 / 300) as u8, ((x + y) / 3) as u8])
+        }));
+        for filter in [
+            ResizeFilter::Nearest,
+            ResizeFilter::Triangle,
+            ResizeFilter::CatmullRom,
+            ResizeFilter::Gaussian,
+            ResizeFilter::Lanczos3,
+        ] {
+            let resize = |backend| {
+                let options = ResizeOptions {
+                    filter,
+                    backend,
+                    ..ResizeOptions::default()
+                };
+                resize_image(&image, (120, 60), (8, 4), options).to_rgb8()
+            };
+            let (simd, reference) = (resize(ResizeBackend::Simd), resize(ResizeBackend::Image));
+            assert_eq!(simd.dimensions(), reference.dimensions());
+            let off = simd
+                .iter()
+                .zip(reference.iter())
+                .map(|(a, b)| u32::from(a.abs_diff(*b)))
+                .max()
+                .unwrap();
+            assert!(off <= 3, "{filter:?} is off by up to {off}");
+        }
+    }
+}
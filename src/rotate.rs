@@ -0,0 +1,307 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Context};
+use chrono::{DateTime, Utc};
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    app::{App, TileLayout},
+    build_cache::{CellCache, CELL_CACHE_LIMIT},
+    cdragon::CDragon,
+    palette::{arrange, Arrangement, SplashColors},
+    paths,
+    sidecar::Sidecar,
+    tiled_splash::{build_tile, BuildOptions},
+};
+
+/// How many times a subset is redrawn before giving up on finding a distinct one
+const MAX_ATTEMPTS: usize = 32;
+
+/// Describes a generated series of wallpapers, written next to them as `manifest.json`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RotationManifest {
+    pub name: String,
+    pub created: DateTime<Utc>,
+    pub seed: u64,
+    pub arrangement: Arrangement,
+    pub wallpapers: Vec<RotationEntry>,
+}
+
+/// A single wallpaper in a rotation
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RotationEntry {
+    pub index: usize,
+    /// The splashes used, in the order they were placed
+    pub splash_ids: Vec<u64>,
+    /// One built tile per monitor, in the same order as the monitors
+    pub files: Vec<RotationFile>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RotationFile {
+    pub monitor: (u32, u32),
+    pub path: PathBuf,
+    /// The wallpaper's sidecar, see [`crate::sidecar::Sidecar`]
+    #[serde(default)]
+    pub layout: PathBuf,
+}
+
+/// Settings for a rotation
+#[derive(Debug, Clone)]
+pub struct Rotation {
+    pub name: String,
+    /// How many wallpapers to generate
+    pub count: usize,
+    /// How many splashes each wallpaper uses, all of them when `None`
+    pub per_wallpaper: Option<usize>,
+    pub arrangement: Arrangement,
+    pub layout: TileLayout,
+    pub seed: u64,
+    /// How the wallpapers are encoded
+    pub build: BuildOptions,
+}
+
+impl Rotation {
+    pub fn new(name: impl Into<String>, count: usize) -> Self {
+        Self {
+            name: name.into(),
+            count,
+            per_wallpaper: None,
+            arrangement: Arrangement::None,
+            layout: TileLayout::default(),
+            seed: Utc::now().timestamp_millis() as u64,
+            build: BuildOptions::default(),
+        }
+    }
+
+    /// Pick the splashes for every wallpaper in the rotation
+    ///
+    /// Every wallpaper gets a different subset of `ids`, or a different order when each
+    /// wallpaper uses all of them. Splashes are drawn from a shuffled deck so they're used
+    /// evenly across the series, then ordered by the arrangement using `colors`.
+    pub fn plan(
+        &self,
+        ids: &[u64],
+        colors: &HashMap<u64, SplashColors>,
+    ) -> anyhow::Result<Vec<Vec<u64>>> {
+        if ids.is_empty() {
+            return Err(anyhow!("there aren't any splashes to rotate through"));
+        }
+        let size = self.per_wallpaper.unwrap_or(ids.len()).clamp(1, ids.len());
+        let subsets = size < ids.len();
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let mut deck: Vec<u64> = Vec::new();
+        let mut seen: HashSet<Vec<u64>> = HashSet::new();
+        let mut plan = Vec::with_capacity(self.count);
+
+        while plan.len() < self.count {
+            let mut attempts = 0;
+            let selection = loop {
+                let mut selection: Vec<u64> = Vec::with_capacity(size);
+                while selection.len() < size {
+                    if deck.is_empty() {
+                        deck = ids.to_vec();
+                        deck.shuffle(&mut rng);
+                    }
+                    let id = deck.pop().unwrap();
+                    if !selection.contains(&id) {
+                        selection.push(id);
+                    }
+                }
+                // arranging re-orders the selection, so it's only distinct if it still is after
+                let selection = arrange(&selection, colors, self.arrangement);
+                let mut key = selection.clone();
+                if subsets {
+                    key.sort();
+                }
+                if seen.insert(key) {
+                    break selection;
+                }
+                attempts += 1;
+                if attempts == MAX_ATTEMPTS {
+                    let hint = match !subsets && self.arrangement != Arrangement::None {
+                        true => ", arranged wallpapers that use every splash all come out the same",
+                        false => "",
+                    };
+                    return Err(anyhow!(
+                        "only found {} distinct wallpapers out of the {} requested{hint}",
+                        plan.len(),
+                        self.count
+                    ));
+                }
+            };
+            plan.push(selection);
+        }
+        Ok(plan)
+    }
+
+    /// Build every wallpaper in the rotation into `out_dir`, with a sidecar for each, and write
+    /// its manifest
+    ///
+    /// The splashes are taken from `app`'s downloads and sized for its monitors.
+    pub fn generate(
+        &self,
+        ids: &[u64],
+        app: &App,
+        cdragon: &CDragon,
+        out_dir: &Path,
+    ) -> anyhow::Result<RotationManifest> {
+        if !out_dir.exists() {
+            fs::create_dir_all(out_dir)
+                .with_context(|| format!("failed to create rotation dir {:?}", out_dir))?;
+        }
+        let cells = CellCache::open()?;
+        let wallpapers = self
+            .plan(ids, &app.colors(&cdragon.patch))?
+            .into_iter()
+            .enumerate()
+            .map(|(index, splash_ids)| {
+                let splash_paths: Vec<PathBuf> = splash_ids
+                    .iter()
+                    .map(|id| app.splash_path(*id, &cdragon.patch))
+                    .collect();
+                let files = app
+                    .monitors
+                    .iter()
+                    .enumerate()
+                    .map(|(monitor_index, monitor)| {
+                        let built = build_tile(
+                            &splash_paths,
+                            *monitor,
+                            self.layout.min_cell,
+                            format!(
+                                "{}-{index:03}-{monitor_index}",
+                                paths::file_name(&self.name)
+                            ),
+                            out_dir,
+                            self.build,
+                            &cells,
+                        )?;
+                        let Some(built) = built else {
+                            return Ok(RotationFile {
+                                monitor: *monitor,
+                                path: PathBuf::default(),
+                                layout: PathBuf::default(),
+                            });
+                        };
+                        let layout = Sidecar::new(
+                            &built.path,
+                            *monitor,
+                            &built.geometry,
+                            &splash_ids,
+                            &splash_paths,
+                            cdragon,
+                        )?
+                        .save(&built.path)?;
+                        Ok(RotationFile {
+                            monitor: *monitor,
+                            path: built.path,
+                            layout,
+                        })
+                    })
+                    .collect::<anyhow::Result<_>>()?;
+                Ok(RotationEntry {
+                    index,
+                    splash_ids,
+                    files,
+                })
+            })
+            .collect::<anyhow::Result<_>>()?;
+        cells.prune(CELL_CACHE_LIMIT)?;
+
+        let manifest = RotationManifest {
+            name: self.name.clone(),
+            created: Utc::now(),
+            seed: self.seed,
+            arrangement: self.arrangement,
+            wallpapers,
+        };
+        fs::write(
+            out_dir.join("manifest.json"),
+            serde_json::to_string_pretty(&manifest)?,
+        )
+        .with_context(|| "failed to write the rotation manifest")?;
+        Ok(manifest)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::{HashMap, HashSet};
+
+    use crate::palette::{Arrangement, SplashColors};
+
+    use super::Rotation;
+
+    #[test]
+    fn distinct_subsets() {
+        let rotation = Rotation {
+            per_wallpaper: Some(3),
+            seed: 7,
+            ..Rotation::new("test", 10)
+        };
+        let plan = rotation.plan(&[1, 2, 3, 4, 5, 6], &HashMap::new()).unwrap();
+        assert_eq!(plan.len(), 10);
+        let mut seen = HashSet::new();
+        for selection in plan {
+            assert_eq!(selection.len(), 3);
+            let mut sorted = selection.clone();
+            sorted.sort();
+            sorted.dedup();
+            assert_eq!(sorted.len(), 3);
+            assert!(seen.insert(sorted));
+        }
+    }
+
+    #[test]
+    fn distinct_orders() {
+        let rotation = Rotation {
+            seed: 7,
+            ..Rotation::new("test", 6)
+        };
+        let plan = rotation.plan(&[1, 2, 3], &HashMap::new()).unwrap();
+        let unique: HashSet<Vec<u64>> = plan.into_iter().collect();
+        assert_eq!(unique.len(), 6);
+    }
+
+    #[test]
+    fn too_many_wallpapers() {
+        let rotation = Rotation::new("test", 3);
+        assert!(rotation.plan(&[1, 2], &HashMap::new()).is_err());
+        assert!(rotation.plan(&[], &HashMap::new()).is_err());
+    }
+
+    #[test]
+    fn arranged_wallpapers_stay_distinct() {
+        let colors: HashMap<u64, SplashColors> = [(1, 0.9), (2, 0.1), (3, 0.5), (4, 0.3)]
+            .into_iter()
+            .map(|(id, luminance)| {
+                let splash = SplashColors {
+                    luminance,
+                    ..SplashColors::default()
+                };
+                (id, splash)
+            })
+            .collect();
+        let rotation = Rotation {
+            arrangement: Arrangement::Luminance,
+            seed: 7,
+            ..Rotation::new("test", 3)
+        };
+        // every wallpaper would be the same four splashes, dark to light
+        assert!(rotation.plan(&[1, 2, 3, 4], &colors).is_err());
+
+        let rotation = Rotation {
+            per_wallpaper: Some(2),
+            ..rotation
+        };
+        for selection in rotation.plan(&[1, 2, 3, 4], &colors).unwrap() {
+            assert!(colors[&selection[0]].luminance <= colors[&selection[1]].luminance);
+        }
+    }
+}
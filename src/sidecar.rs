@@ -0,0 +1,228 @@
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Context};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cache::write_atomic,
+    cdragon::CDragon,
+    tiled_splash::{TileGeometry, TileParams},
+};
+
+/// Bumped whenever a field changes meaning, so readers can tell old sidecars apart
+const SIDECAR_FORMAT: u32 = 1;
+
+/// Which skin sits where in a built wallpaper, saved next to it with a `.json` extension
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Sidecar {
+    pub format: u32,
+    /// The wallpaper's file name
+    pub image: String,
+    pub monitor: (u32, u32),
+    pub canvas: (u32, u32),
+    /// The grid the wallpaper was built from, enough to build it again with the same geometry
+    pub params: TileParams,
+    /// Left to right, top to bottom
+    pub cells: Vec<SidecarCell>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SidecarCell {
+    /// Where the cell is on the canvas
+    pub rect: Rect,
+    pub skin_id: u64,
+    pub skin_name: String,
+    pub champion: String,
+    /// The part of the source splash the cell shows, in the splash's own pixels
+    pub crop: Rect,
+    pub source: PathBuf,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Sidecar {
+    /// Describe the wallpaper at `image` built from `splash_ids`, whose splashes are at
+    /// `splash_paths` in the same order
+    pub fn new(
+        image: &Path,
+        monitor: (u32, u32),
+        geometry: &TileGeometry,
+        splash_ids: &[u64],
+        splash_paths: &[PathBuf],
+        cdragon: &CDragon,
+    ) -> anyhow::Result<Self> {
+        // Splashes repeat across cells, so each is only read once
+        let mut source_dims = HashMap::new();
+        let cells = geometry
+            .cells
+            .iter()
+            .map(|cell| {
+                let (id, source) = splash_ids
+                    .get(cell.splash)
+                    .zip(splash_paths.get(cell.splash))
+                    .ok_or(anyhow!("the tile has no splash {}", cell.splash))?;
+                let source_dims = match source_dims.entry(cell.splash) {
+                    Entry::Occupied(dims) => *dims.get(),
+                    Entry::Vacant(entry) => *entry.insert(
+                        image::image_dimensions(source)
+                            .with_context(|| format!("failed to read the size of {:?}", source))?,
+                    ),
+                };
+                let skin = cdragon.skin(*id);
+                let champion = skin
+                    .and_then(|skin| cdragon.champions.get(&skin.champion_id()))
+                    .map(|champion| champion.name.clone());
+                Ok(SidecarCell {
+                    rect: Rect {
+                        x: cell.x,
+                        y: cell.y,
+                        width: cell.width,
+                        height: cell.height,
+                    },
+                    skin_id: *id,
+                    skin_name: skin.map(|skin| skin.name.clone()).unwrap_or_default(),
+                    champion: champion.unwrap_or_default(),
+                    crop: source_crop(source_dims, &geometry.params),
+                    source: source.clone(),
+                })
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Self {
+            format: SIDECAR_FORMAT,
+            image: image
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            monitor,
+            canvas: geometry.canvas,
+            params: geometry.params,
+            cells,
+        })
+    }
+
+    /// Where the sidecar for the wallpaper at `image` goes
+    pub fn path(image: &Path) -> PathBuf {
+        image.with_extension("json")
+    }
+
+    /// Save next to the wallpaper at `image`, returning where it went
+    pub fn save(&self, image: &Path) -> anyhow::Result<PathBuf> {
+        let path = Self::path(image);
+        let json = serde_json::to_string_pretty(self)
+            .with_context(|| format!("failed to serialize the layout of {:?}", image))?;
+        write_atomic(&path, &json)?;
+        Ok(path)
+    }
+
+    pub fn load(image: &Path) -> anyhow::Result<Self> {
+        let path = Self::path(image);
+        let json =
+            std::fs::read_to_string(&path).with_context(|| format!("failed to read {:?}", path))?;
+        serde_json::from_str(&json).with_context(|| format!("failed to deserialize {:?}", path))
+    }
+}
+
+/// The part of a splash of `source` size that a cell of `params` shows
+///
+/// Splashes are scaled to cover [`TileParams::image_res`], cropped to it around their center and
+/// then cropped again by [`TileParams::image_adjust`], see [`crate::resize::resize_image`].
+fn source_crop(source: (u32, u32), params: &TileParams) -> Rect {
+    let (width, height) = (f64::from(source.0), f64::from(source.1));
+    let (res_x, res_y) = (f64::from(params.image_res.0), f64::from(params.image_res.1));
+    let scale = f64::max(res_x / width, res_y / height);
+    let cell = params.cell();
+    let x = ((width * scale - res_x) / 2.0 + f64::from(params.image_adjust.0 / 2)) / scale;
+    let y = ((height * scale - res_y) / 2.0 + f64::from(params.image_adjust.1 / 2)) / scale;
+    Rect {
+        x: x.round() as u32,
+        y: y.round() as u32,
+        width: ((f64::from(cell.0) / scale).round() as u32).min(source.0),
+        height: ((f64::from(cell.1) / scale).round() as u32).min(source.1),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        cdragon::fixtures,
+        tiled_splash::{plan_tile, ScoreWeights, TileParams},
+    };
+
+    use super::{source_crop, Rect, Sidecar};
+
+    #[test]
+    fn crops_in_source_pixels() {
+        let params = TileParams {
+            dims: (2, 2),
+            image_res: (600, 300),
+            image_adjust: (100, 0),
+        };
+        // the same shape as the cell, only scaled down and cropped on the sides
+        assert_eq!(
+            source_crop((1200, 600), &params),
+            Rect {
+                x: 100,
+                y: 0,
+                width: 1000,
+                height: 600,
+            }
+        );
+        // a taller splash loses its top and bottom first
+        assert_eq!(
+            source_crop((600, 600), &params),
+            Rect {
+                x: 50,
+                y: 150,
+                width: 500,
+                height: 300,
+            }
+        );
+    }
+
+    #[test]
+    fn describes_every_cell() {
+        let ids = [1000, 103015];
+        let splash_paths: Vec<_> = ids
+            .iter()
+            .map(|id| fixtures::splash(&format!("tiles/{id}.jpg"), (160, 90), [0, 0, 0]))
+            .collect();
+        let dir = splash_paths[0].parent().unwrap();
+        let geometry = plan_tile((160, 90), (640, 360), 2, (0, 0), &ScoreWeights::default())
+            .unwrap()
+            .unwrap();
+        let image = dir.join("tile-0.jpg");
+        let sidecar = Sidecar::new(
+            &image,
+            (640, 360),
+            &geometry,
+            &ids,
+            &splash_paths,
+            &fixtures::cdragon(),
+        )
+        .unwrap();
+        assert_eq!(sidecar.image, "tile-0.jpg");
+        assert_eq!(sidecar.cells.len(), geometry.cells.len());
+        let ahri = &sidecar.cells[1];
+        assert_eq!(
+            (
+                ahri.skin_id,
+                ahri.skin_name.as_str(),
+                ahri.champion.as_str()
+            ),
+            (103015, "Star Guardian Ahri", "Ahri")
+        );
+        assert_eq!(ahri.source, splash_paths[1]);
+
+        assert_eq!(sidecar.save(&image).unwrap(), dir.join("tile-0.json"));
+        assert_eq!(Sidecar::load(&image).unwrap(), sidecar);
+    }
+}
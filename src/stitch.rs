@@ -0,0 +1,215 @@
+use std::borrow::Cow;
+
+use anyhow::anyhow;
+use image::{imageops::FilterType, DynamicImage, GenericImage, Rgba, RgbaImage};
+
+/// How stitched images are placed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    /// Side by side, left to right
+    Horizontal,
+    /// Top to bottom
+    Vertical,
+    /// Left to right in rows of `columns`, top to bottom
+    Grid { columns: u32 },
+}
+
+/// Where an image sits in a row or column bigger than it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Align {
+    Start,
+    #[default]
+    Center,
+    End,
+}
+
+impl Align {
+    fn offset(self, space: u32, size: u32) -> u32 {
+        match self {
+            Align::Start => 0,
+            Align::Center => (space - size) / 2,
+            Align::End => space - size,
+        }
+    }
+}
+
+/// Whether images are scaled to a common size first, heights when they're placed in rows and
+/// widths when they're stacked in a column
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Normalize {
+    /// Keep every image's own size
+    #[default]
+    Off,
+    /// Shrink to the smallest
+    Smallest,
+    /// Enlarge to the largest
+    Largest,
+    /// Scale to this many pixels
+    To(u32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Stitch {
+    pub layout: Layout,
+    pub align: Align,
+    pub normalize: Normalize,
+    /// Pixels left between images
+    pub gap: u32,
+    /// What's left uncovered, gaps included
+    pub background: Rgba<u8>,
+}
+
+impl Stitch {
+    pub fn new(layout: Layout) -> Self {
+        Self {
+            layout,
+            align: Align::default(),
+            normalize: Normalize::default(),
+            gap: 0,
+            background: Rgba([0, 0, 0, 0]),
+        }
+    }
+
+    /// Combine `images` into one, in order
+    ///
+    /// Each grid column is as wide as its widest image and each row as tall as its tallest,
+    /// images smaller than their cell are placed by [`Stitch::align`] on both axes.
+    pub fn stitch(&self, images: &[DynamicImage]) -> anyhow::Result<RgbaImage> {
+        if images.is_empty() {
+            return Err(anyhow!("there's nothing to stitch"));
+        }
+        let columns = match self.layout {
+            Layout::Horizontal => images.len(),
+            Layout::Vertical => 1,
+            Layout::Grid { columns: 0 } => return Err(anyhow!("a grid needs at least 1 column")),
+            Layout::Grid { columns } => columns as usize,
+        };
+        let images = self.normalized(images);
+        let rows = images.len().div_ceil(columns);
+        let mut widths = vec![0; columns];
+        let mut heights = vec![0; rows];
+        for (index, image) in images.iter().enumerate() {
+            let (column, row) = (index % columns, index / columns);
+            widths[column] = widths[column].max(image.width());
+            heights[row] = heights[row].max(image.height());
+        }
+        let starts = |sizes: &[u32]| -> Vec<u32> {
+            sizes
+                .iter()
+                .scan(0, |start, size| {
+                    let this = *start;
+                    *start += size + self.gap;
+                    Some(this)
+                })
+                .collect()
+        };
+        let (xs, ys) = (starts(&widths), starts(&heights));
+        let span = |sizes: &[u32]| sizes.iter().sum::<u32>() + self.gap * (sizes.len() as u32 - 1);
+        let mut stitched = RgbaImage::from_pixel(span(&widths), span(&heights), self.background);
+        for (index, image) in images.iter().enumerate() {
+            let (column, row) = (index % columns, index / columns);
+            stitched.copy_from(
+                image.as_ref(),
+                xs[column] + self.align.offset(widths[column], image.width()),
+                ys[row] + self.align.offset(heights[row], image.height()),
+            )?;
+        }
+        Ok(stitched)
+    }
+
+    fn normalized<'a>(&self, images: &'a [DynamicImage]) -> Vec<Cow<'a, DynamicImage>> {
+        // stacked images share widths, everything else shares heights
+        let side = |image: &DynamicImage| match self.layout {
+            Layout::Vertical => image.width(),
+            _ => image.height(),
+        };
+        let target = match self.normalize {
+            Normalize::Off => None,
+            Normalize::Smallest => images.iter().map(side).min(),
+            Normalize::Largest => images.iter().map(side).max(),
+            Normalize::To(size) => Some(size),
+        };
+        images
+            .iter()
+            .map(|image| match target {
+                Some(target) if side(image) != target && side(image) > 0 => {
+                    let scale = f64::from(target) / f64::from(side(image));
+                    let scaled = |size: u32| ((f64::from(size) * scale).round() as u32).max(1);
+                    let (width, height) = match self.layout {
+                        Layout::Vertical => (target, scaled(image.height())),
+                        _ => (scaled(image.width()), target),
+                    };
+                    Cow::Owned(image.resize_exact(width, height, FilterType::Lanczos3))
+                }
+                _ => Cow::Borrowed(image),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use image::{DynamicImage, Rgba, RgbaImage};
+
+    use super::{Align, Layout, Normalize, Stitch};
+
+    fn image(width: u32, height: u32, red: u8) -> DynamicImage {
+        DynamicImage::ImageRgba8(RgbaImage::from_pixel(width, height, Rgba([red, 0, 0, 255])))
+    }
+
+    #[test]
+    fn layouts() {
+        let images = [image(4, 2, 10), image(2, 4, 20), image(3, 3, 30)];
+        let horizontal = Stitch::new(Layout::Horizontal).stitch(&images).unwrap();
+        assert_eq!(horizontal.dimensions(), (9, 4));
+        // the short first image is centered, leaving background above it
+        assert_eq!(horizontal.get_pixel(0, 0), &Rgba([0, 0, 0, 0]));
+        assert_eq!(horizontal.get_pixel(0, 1)[0], 10);
+
+        let vertical = Stitch {
+            gap: 1,
+            align: Align::Start,
+            ..Stitch::new(Layout::Vertical)
+        }
+        .stitch(&images)
+        .unwrap();
+        assert_eq!(vertical.dimensions(), (4, 11));
+        assert_eq!(vertical.get_pixel(0, 3)[0], 20);
+        assert_eq!(vertical.get_pixel(3, 3)[3], 0);
+
+        let grid = Stitch::new(Layout::Grid { columns: 2 })
+            .stitch(&images)
+            .unwrap();
+        assert_eq!(grid.dimensions(), (6, 7));
+        assert_eq!(grid.get_pixel(0, 4)[0], 30);
+
+        assert!(Stitch::new(Layout::Horizontal).stitch(&[]).is_err());
+        assert!(Stitch::new(Layout::Grid { columns: 0 })
+            .stitch(&images)
+            .is_err());
+    }
+
+    #[test]
+    fn normalized_heights() {
+        let images = [image(8, 4, 10), image(4, 8, 20)];
+        let stitch = |normalize| {
+            Stitch {
+                normalize,
+                ..Stitch::new(Layout::Horizontal)
+            }
+            .stitch(&images)
+            .unwrap()
+            .dimensions()
+        };
+        assert_eq!(stitch(Normalize::Smallest), (10, 4));
+        assert_eq!(stitch(Normalize::Largest), (20, 8));
+        assert_eq!(stitch(Normalize::To(2)), (5, 2));
+        let widths = Stitch {
+            normalize: Normalize::Smallest,
+            ..Stitch::new(Layout::Vertical)
+        }
+        .stitch(&images)
+        .unwrap();
+        assert_eq!(widths.dimensions(), (4, 10));
+    }
+}
@@ -1,175 +1,775 @@
-use image::{DynamicImage, GenericImage, GenericImageView, ImageBuffer, RgbaImage};
-use std::path::PathBuf;
+use anyhow::Context;
+use image::{
+    codecs::{jpeg::JpegEncoder, png::PngEncoder},
+    ColorType, DynamicImage, GenericImage, GenericImageView, ImageEncoder, RgbImage,
+};
+use std::{
+    collections::HashMap,
+    io::{BufWriter, Cursor, Write},
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
 
 use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Default)]
+use crate::{
+    build_cache::CellCache,
+    cache::write_atomic_with,
+    resize::{load_scaled, resize_cell, resize_image, ResizeOptions},
+};
+
+/// The grid a tile is built from
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub struct TileParams {
-    dims: (u32, u32),
-    image_res: (u32, u32),
-    image_adjust: (u32, u32),
+    /// Columns and rows of cells
+    pub dims: (u32, u32),
+    /// The size splashes are scaled to, keeping their aspect ratio
+    pub image_res: (u32, u32),
+    /// How much is cropped off each scaled splash to make a cell, split between both sides
+    pub image_adjust: (u32, u32),
+}
+
+impl TileParams {
+    /// The size of each cell
+    pub fn cell(&self) -> (u32, u32) {
+        (
+            self.image_res.0 - self.image_adjust.0,
+            self.image_res.1 - self.image_adjust.1,
+        )
+    }
 }
 
+/// How much each flaw of a grid counts against it, higher weights avoid that flaw harder
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScoreWeights {
+    /// The share of each splash cropped off to fit its cell
+    pub crop: f64,
+    /// How far splashes are enlarged past their own size, which blurs them
+    pub distortion: f64,
+    /// The share of cells that repeat a splash because there are more cells than splashes
+    pub count: f64,
+}
+
+impl Default for ScoreWeights {
+    fn default() -> Self {
+        Self {
+            crop: 1.0,
+            distortion: 0.5,
+            count: 1.0,
+        }
+    }
+}
+
+impl ScoreWeights {
+    /// How bad `params` is for splashes of `image_res` and `image_count` of them, lower is
+    /// better
+    pub fn score(&self, params: &TileParams, image_res: (u32, u32), image_count: u32) -> f64 {
+        let (cell_x, cell_y) = params.cell();
+        let scaled = f64::from(params.image_res.0) * f64::from(params.image_res.1);
+        let crop = 1.0 - f64::from(cell_x) * f64::from(cell_y) / scaled;
+        let scale = f64::from(params.image_res.0) / f64::from(image_res.0);
+        let distortion = (scale - 1.0).max(0.0);
+        let cells = f64::from(params.dims.0) * f64::from(params.dims.1);
+        let count = 1.0 - f64::from(image_count) / cells;
+        self.crop * crop + self.distortion * distortion + self.count * count
+    }
+}
+
+/// The best grid to cover `container_res` with at least `minimum_image_count` splashes of
+/// `image_res`, each cell at least `minimum_image_dims`, or `None` when no grid fits
 pub fn find_optimal_tile(
     image_res: (u32, u32),
     container_res: (u32, u32),
     minimum_image_count: u32,
     minimum_image_dims: (u32, u32),
 ) -> Option<TileParams> {
-    let mut c = 0;
-    let mut best_fit: Option<TileParams> = None;
-    loop {
-        let x = calculate_tile_x_bias(image_res, container_res, c);
-        let y = calculate_tile_y_bias(image_res, container_res, c);
-        let mut temp_best: TileParams = TileParams::default();
-
-        if x.image_adjust.1 > y.image_adjust.0 {
-            temp_best = y;
-        } else {
-            temp_best = x;
+    find_optimal_tile_with(
+        image_res,
+        container_res,
+        minimum_image_count,
+        minimum_image_dims,
+        &ScoreWeights::default(),
+    )
+}
+
+/// [`find_optimal_tile`] with grids scored by `weights`
+pub fn find_optimal_tile_with(
+    image_res: (u32, u32),
+    container_res: (u32, u32),
+    minimum_image_count: u32,
+    minimum_image_dims: (u32, u32),
+    weights: &ScoreWeights,
+) -> Option<TileParams> {
+    let count = minimum_image_count.max(1);
+    candidates(image_res, container_res, count, minimum_image_dims)
+        .map(|params| (weights.score(&params, image_res, count), params))
+        .min_by(|(a, _), (b, _)| a.total_cmp(b))
+        .map(|(_, params)| params)
+}
+
+/// Every grid worth scoring
+///
+/// For each column count that's the fewest rows that fit every splash, plus the row counts on
+/// either side of the splashes' own aspect ratio, since more rows than that only crop more.
+fn candidates(
+    image_res: (u32, u32),
+    container_res: (u32, u32),
+    count: u32,
+    minimum_image_dims: (u32, u32),
+) -> impl Iterator<Item = TileParams> {
+    let empty =
+        image_res.0 == 0 || image_res.1 == 0 || container_res.0 == 0 || container_res.1 == 0;
+    let max_columns = match empty {
+        true => 0,
+        false => (container_res.0 / minimum_image_dims.0.max(1))
+            // a row holding twice the splashes already repeats most of them
+            .min(count.saturating_mul(2).saturating_add(8)),
+    };
+    let max_rows = container_res.1 / minimum_image_dims.1.max(1);
+    (1..=max_columns).flat_map(move |columns| {
+        let fewest = count.div_ceil(columns);
+        let cell_x = container_res.0.div_ceil(columns);
+        let matched = f64::from(container_res.1) * f64::from(image_res.0)
+            / (f64::from(cell_x) * f64::from(image_res.1));
+        let mut rows = vec![fewest, matched.floor() as u32, matched.ceil() as u32];
+        rows.sort();
+        rows.dedup();
+        rows.into_iter()
+            .filter(move |rows| *rows >= fewest && *rows <= max_rows)
+            .map(move |rows| fit((columns, rows), image_res, container_res))
+            .filter(move |params| {
+                let cell = params.cell();
+                cell.0 >= minimum_image_dims.0 && cell.1 >= minimum_image_dims.1
+            })
+    })
+}
+
+/// Splashes scaled to cover the cells of a `dims` grid over `container_res`, rounding cells up
+/// so the grid is never smaller than the container
+fn fit(dims: (u32, u32), image_res: (u32, u32), container_res: (u32, u32)) -> TileParams {
+    let cell = (
+        container_res.0.div_ceil(dims.0),
+        container_res.1.div_ceil(dims.1),
+    );
+    let scale = f64::max(
+        f64::from(cell.0) / f64::from(image_res.0),
+        f64::from(cell.1) / f64::from(image_res.1),
+    );
+    let scaled = |side: u32, cell: u32| ((f64::from(side) * scale).round() as u32).max(cell);
+    let image_res = (scaled(image_res.0, cell.0), scaled(image_res.1, cell.1));
+    TileParams {
+        dims,
+        image_res,
+        image_adjust: (image_res.0 - cell.0, image_res.1 - cell.1),
+    }
+}
+
+/// How built tiles are laid out and encoded
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BuildOptions {
+    /// The JPEG quality, 1 to 100
+    pub quality: u8,
+    /// Roughly how many bytes a build may hold at once
+    ///
+    /// Tiles too big to hold whole are drawn a strip at a time and saved as PNGs instead of
+    /// JPEGs.
+    pub memory_limit: u64,
+    pub resize: ResizeOptions,
+    /// How grids are picked
+    pub weights: ScoreWeights,
+}
+
+impl Default for BuildOptions {
+    fn default() -> Self {
+        Self {
+            quality: 75,
+            memory_limit: 1024 * MB,
+            resize: ResizeOptions::default(),
+            weights: ScoreWeights::default(),
         }
+    }
+}
+
+pub const MB: u64 = 1024 * 1024;
+
+/// Where the cells of a tile go, splashes repeat in order once they run out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Grid {
+    columns: u32,
+    rows: u32,
+    cell: (u32, u32),
+    splashes: usize,
+}
 
-        if (temp_best.dims.0 * temp_best.dims.1 > minimum_image_count)
-            && temp_best.image_res >= minimum_image_dims
-        {
-            best_fit = Some(temp_best);
-            break;
+impl Grid {
+    fn new(params: &TileParams, splashes: usize) -> Self {
+        Self {
+            columns: params.dims.0,
+            rows: params.dims.1,
+            cell: params.cell(),
+            splashes,
         }
+    }
+
+    fn width(&self) -> u32 {
+        self.columns * self.cell.0
+    }
+
+    fn height(&self) -> u32 {
+        self.rows * self.cell.1
+    }
 
-        if temp_best.image_res < minimum_image_dims {
-            break;
+    /// The size in bytes of `rows` rows of cells
+    fn bytes(&self, rows: u32) -> u64 {
+        u64::from(self.width()) * u64::from(rows * self.cell.1) * 3
+    }
+
+    /// How many rows of cells to draw at once in `budget` bytes, leaving as much again for the
+    /// cells being resized
+    fn rows_per_batch(&self, budget: u64) -> u32 {
+        (budget / (2 * self.bytes(1)).max(1)).clamp(1, u64::from(self.rows)) as u32
+    }
+
+    /// Every splash drawn in `rows`, with the top left corners of its cells measured from the
+    /// first of the rows
+    fn placements(&self, rows: Range<u32>) -> HashMap<usize, Vec<(u32, u32)>> {
+        let mut placements: HashMap<usize, Vec<(u32, u32)>> = HashMap::new();
+        for row in rows.clone() {
+            for column in 0..self.columns {
+                let index = (row * self.columns + column) as usize % self.splashes;
+                placements
+                    .entry(index)
+                    .or_default()
+                    .push((column * self.cell.0, (row - rows.start) * self.cell.1));
+            }
+        }
+        placements
+    }
+
+    fn geometry(&self, params: TileParams) -> TileGeometry {
+        let crop = (
+            params.image_adjust.0 / 2,
+            params.image_adjust.1 / 2,
+            self.cell.0,
+            self.cell.1,
+        );
+        let cells = (0..self.rows)
+            .flat_map(|row| (0..self.columns).map(move |column| (column, row)))
+            .map(|(column, row)| CellPlacement {
+                splash: (row * self.columns + column) as usize % self.splashes,
+                x: column * self.cell.0,
+                y: row * self.cell.1,
+                width: self.cell.0,
+                height: self.cell.1,
+                crop,
+            })
+            .collect();
+        TileGeometry {
+            canvas: (self.width(), self.height()),
+            params,
+            cells,
         }
-        c += 1;
     }
-    best_fit
 }
 
-pub fn calculate_tile_y_bias(
+/// Where every cell of a tile is and what it shows
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TileGeometry {
+    pub canvas: (u32, u32),
+    pub params: TileParams,
+    /// Left to right, top to bottom
+    pub cells: Vec<CellPlacement>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CellPlacement {
+    /// The index of the splash in the order they were given
+    pub splash: usize,
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    /// The part of the splash shown, as x, y, width and height once it's scaled to
+    /// [`TileParams::image_res`]
+    pub crop: (u32, u32, u32, u32),
+}
+
+/// The grid for `count` splashes of `image_res` on `monitor`, `None` when no layout fits
+pub fn plan_tile(
     image_res: (u32, u32),
-    container_res: (u32, u32),
-    c: u32,
-) -> TileParams {
-    let image_res = (image_res.0 as f32, image_res.1 as f32);
-    let container_res = (container_res.0 as f32, container_res.1 as f32);
-    let fit_y = container_res.1 / image_res.1;
-    let target_fit_y = fit_y.ceil() + (c as f32);
-    let new_image_y = container_res.1 / target_fit_y;
-    let image_ar = image_res.0 / image_res.1;
-    let new_image_x = image_ar * new_image_y;
-    let fit_x = container_res.0 / new_image_x;
-    let overfit = fit_x.ceil();
-    let overfit_error = fit_x.ceil() - fit_x;
-    let pixel_x_error = overfit_error * new_image_x;
-    let x_adjust = pixel_x_error / overfit;
-    TileParams {
-        dims: (overfit as u32, target_fit_y as u32),
-        image_res: (new_image_x.round() as u32, new_image_y.round() as u32),
-        image_adjust: (x_adjust.ceil() as u32, 0),
+    monitor: (u32, u32),
+    count: usize,
+    minimum_image_dims: (u32, u32),
+    weights: &ScoreWeights,
+) -> anyhow::Result<Option<TileGeometry>> {
+    let params = find_optimal_tile_with(
+        image_res,
+        monitor,
+        count.try_into()?,
+        minimum_image_dims,
+        weights,
+    );
+    Ok(params.map(|params| Grid::new(&params, count).geometry(params)))
+}
+
+/// A splash handed to [`compose`]
+#[derive(Debug, Clone, Copy)]
+pub enum SplashInput<'a> {
+    Path(&'a Path),
+    Image(&'a DynamicImage),
+    /// An encoded image, like the body of a download
+    Bytes(&'a [u8]),
+}
+
+impl SplashInput<'_> {
+    fn dimensions(&self) -> anyhow::Result<(u32, u32)> {
+        match self {
+            SplashInput::Path(path) => image::image_dimensions(path)
+                .with_context(|| format!("failed to read the size of {:?}", path)),
+            SplashInput::Image(image) => Ok(image.dimensions()),
+            SplashInput::Bytes(bytes) => Ok(image::io::Reader::new(Cursor::new(bytes))
+                .with_guessed_format()?
+                .into_dimensions()?),
+        }
+    }
+
+    /// The splash resized into a cell of `params`, through `cells` when it's a path
+    fn cell(
+        &self,
+        params: &TileParams,
+        resize: ResizeOptions,
+        cells: Option<&CellCache>,
+    ) -> anyhow::Result<DynamicImage> {
+        let (size, crop) = (params.image_res, params.image_adjust);
+        match (self, cells) {
+            (SplashInput::Path(path), Some(cells)) => cells
+                .cell(path, size, crop, resize, || {
+                    resize_cell(path, size, crop, resize)
+                })
+                .with_context(|| format!("failed to draw {:?}", path)),
+            (SplashInput::Path(path), None) => resize_cell(path, size, crop, resize)
+                .with_context(|| format!("failed to draw {:?}", path)),
+            (SplashInput::Image(image), _) => Ok(resize_image(image, size, crop, resize)),
+            (SplashInput::Bytes(bytes), _) => {
+                let image = load_scaled(bytes, size, resize.fast_decode)?;
+                Ok(resize_image(&image, size, crop, resize))
+            }
+        }
     }
 }
-pub fn calculate_tile_x_bias(
-    image_res: (u32, u32),
-    container_res: (u32, u32),
-    c: u32,
-) -> TileParams {
-    let image_res = (image_res.0 as f32, image_res.1 as f32);
-    let container_res = (container_res.0 as f32, container_res.1 as f32);
-    let fit_x = container_res.0 / image_res.0;
-    let target_fit_x = fit_x.ceil() + (c as f32);
-    let new_image_x = container_res.0 / target_fit_x;
-    let image_ar = image_res.0 / image_res.1;
-    let new_image_y = new_image_x / image_ar;
-    let fit_y = container_res.1 / new_image_y;
-    let overfit = fit_y.ceil();
-    let overfit_error = fit_y.ceil() - fit_y;
-    let pixel_y_error = overfit_error * new_image_y;
-    let y_adjust = pixel_y_error / overfit;
-    TileParams {
-        dims: (target_fit_x.round() as u32, overfit.round() as u32),
-        image_res: (new_image_x.round() as u32, new_image_y.round() as u32),
-        image_adjust: (0, y_adjust.ceil() as u32),
+
+/// A tile built in memory
+#[derive(Debug, Clone)]
+pub struct Composition {
+    pub image: RgbImage,
+    pub geometry: TileGeometry,
+}
+
+/// How [`Composition::encode`] encodes a tile
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    /// At this quality, 1 to 100
+    Jpeg(u8),
+    Png,
+}
+
+impl Composition {
+    /// Draw the tile `geometry` lays out, holding the canvas and a batch of rows of cells at a
+    /// time within [`BuildOptions::memory_limit`]
+    fn draw(
+        splashes: &[SplashInput],
+        geometry: TileGeometry,
+        options: BuildOptions,
+        cells: Option<&CellCache>,
+    ) -> anyhow::Result<Self> {
+        let mut image = RgbImage::new(geometry.canvas.0, geometry.canvas.1);
+        let canvas = u64::from(image.width()) * u64::from(image.height()) * 3;
+        let budget = options.memory_limit.saturating_sub(canvas);
+        draw_strips(splashes, &geometry, options, cells, budget, |y, strip| {
+            Ok(image.copy_from(&strip, 0, y)?)
+        })?;
+        Ok(Self { image, geometry })
+    }
+
+    pub fn encode(&self, encoding: Encoding) -> anyhow::Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        self.write(&mut bytes, encoding)?;
+        Ok(bytes)
+    }
+
+    fn write(&self, writer: impl Write, encoding: Encoding) -> anyhow::Result<()> {
+        let (width, height) = self.image.dimensions();
+        match encoding {
+            Encoding::Jpeg(quality) => JpegEncoder::new_with_quality(writer, quality).write_image(
+                &self.image,
+                width,
+                height,
+                ColorType::Rgb8,
+            )?,
+            Encoding::Png => {
+                PngEncoder::new(writer).write_image(&self.image, width, height, ColorType::Rgb8)?
+            }
+        }
+        Ok(())
     }
 }
 
+/// Build a tile from `splashes` in memory, placed in the given order, without touching the
+/// disk unless a splash is a path. `None` when no layout fits.
+pub fn compose(
+    splashes: &[SplashInput],
+    monitor: (u32, u32),
+    minimum_image_dims: (u32, u32),
+    options: BuildOptions,
+) -> anyhow::Result<Option<Composition>> {
+    compose_with(splashes, monitor, minimum_image_dims, options, None)
+}
+
+/// [`compose`], reusing the cells of splashes given as paths from `cells` and keeping new ones
+/// in it
+pub fn compose_with(
+    splashes: &[SplashInput],
+    monitor: (u32, u32),
+    minimum_image_dims: (u32, u32),
+    options: BuildOptions,
+    cells: Option<&CellCache>,
+) -> anyhow::Result<Option<Composition>> {
+    let Some(geometry) = plan_splashes(splashes, monitor, minimum_image_dims, &options)? else {
+        return Ok(None);
+    };
+    Composition::draw(splashes, geometry, options, cells).map(Some)
+}
+
+/// [`plan_tile`] for splashes shaped like the first of `splashes`
+fn plan_splashes(
+    splashes: &[SplashInput],
+    monitor: (u32, u32),
+    minimum_image_dims: (u32, u32),
+    options: &BuildOptions,
+) -> anyhow::Result<Option<TileGeometry>> {
+    let Some(first) = splashes.first() else {
+        return Ok(None);
+    };
+    plan_tile(
+        first.dimensions()?,
+        monitor,
+        splashes.len(),
+        minimum_image_dims,
+        &options.weights,
+    )
+}
+
+/// A tile saved by [`build_tile`]
+#[derive(Debug, Clone)]
+pub struct BuiltTile {
+    pub path: PathBuf,
+    pub geometry: TileGeometry,
+}
+
+/// Build a tile from `splash_paths`, placed in the given order, and save it to `out_dir`
+///
+/// Tiles that fit in [`BuildOptions::memory_limit`] are composed like [`compose_with`] and saved
+/// as JPEGs, bigger ones are drawn a strip at a time straight into a PNG. Cells already in
+/// `cells` are reused instead of being resized again. Returns `None` when no layout fits.
+///
+/// # Arguments
+/// * `minimum_image_dims` - the smallest each cell may be shrunk to, `(0, 0)` for no limit
 pub fn build_tile(
-    splash_paths: &mut Vec<PathBuf>,
+    splash_paths: &[PathBuf],
     monitor: (u32, u32),
+    minimum_image_dims: (u32, u32),
     name: impl Into<String>,
-) -> PathBuf {
-    if splash_paths.is_empty() {
-        return PathBuf::default();
-    }
-    let image_dims = image::open(splash_paths.first().unwrap())
-        .unwrap()
-        .dimensions();
-    println!("Calculating optimal tile...");
-    let tile_params = find_optimal_tile(
-        image_dims,
-        monitor,
-        splash_paths.len().try_into().unwrap(),
-        (0, 0),
-    );
-    println!("Done");
-    if let Some(params) = tile_params {
-        println!("Building tile...");
-        let adjusted: Vec<DynamicImage> = splash_paths
-            .par_iter_mut()
-            .map(|path| {
-                let image = image::open(path.clone()).unwrap_or_else(|error| {
-                    panic!("Error opening {}: {error}", path.to_str().unwrap())
-                });
-                let image = image.resize(
-                    params.image_res.0,
-                    params.image_res.1,
-                    image::imageops::FilterType::Lanczos3,
-                );
-                image.crop_imm(
-                    params.image_adjust.0 / 2,
-                    params.image_adjust.1 / 2,
-                    params.image_res.0 - (params.image_adjust.0),
-                    params.image_res.1 - (params.image_adjust.1),
+    out_dir: &Path,
+    options: BuildOptions,
+    cells: &CellCache,
+) -> anyhow::Result<Option<BuiltTile>> {
+    let splashes: Vec<SplashInput> = splash_paths
+        .iter()
+        .map(|path| SplashInput::Path(path))
+        .collect();
+    let geometry = plan_splashes(&splashes, monitor, minimum_image_dims, &options)?;
+    let Some(geometry) = geometry else {
+        return Ok(None);
+    };
+    let name = name.into();
+    let (width, height) = geometry.canvas;
+    let in_memory = u64::from(width) * u64::from(height) * 3 <= options.memory_limit;
+    let (path, encoding) = match in_memory {
+        true => (
+            out_dir.join(format!("{name}.jpg")),
+            Encoding::Jpeg(options.quality),
+        ),
+        false => (out_dir.join(format!("{name}.png")), Encoding::Png),
+    };
+    // drawn into a temporary file, so a failed build leaves the last wallpaper as it was
+    write_atomic_with(&path, |file| {
+        let mut output = BufWriter::new(file);
+        match encoding {
+            Encoding::Jpeg(_) => {
+                Composition::draw(&splashes, geometry.clone(), options, Some(cells))?
+                    .write(&mut output, encoding)?;
+            }
+            Encoding::Png => {
+                let mut encoder = png::Encoder::new(&mut output, width, height);
+                encoder.set_color(png::ColorType::Rgb);
+                encoder.set_depth(png::BitDepth::Eight);
+                let mut writer = encoder.write_header()?;
+                let mut stream = writer.stream_writer()?;
+                draw_strips(
+                    &splashes,
+                    &geometry,
+                    options,
+                    Some(cells),
+                    options.memory_limit,
+                    |_, strip| Ok(stream.write_all(strip.as_raw())?),
+                )?;
+                stream.finish()?;
+                writer.finish()?;
+            }
+        }
+        Ok(output.flush()?)
+    })?;
+    Ok(Some(BuiltTile { path, geometry }))
+}
+
+/// Draw the tile `geometry` lays out a batch of rows at a time, each batch taking at most
+/// `budget` bytes, and hand every strip to `sink` with how far down the tile it starts
+fn draw_strips(
+    splashes: &[SplashInput],
+    geometry: &TileGeometry,
+    options: BuildOptions,
+    cells: Option<&CellCache>,
+    budget: u64,
+    mut sink: impl FnMut(u32, RgbImage) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    let grid = Grid::new(&geometry.params, splashes.len());
+    let batch = grid.rows_per_batch(budget);
+    for start in (0..grid.rows).step_by(batch as usize) {
+        let rows = start..(start + batch).min(grid.rows);
+        let mut strip = RgbImage::new(grid.width(), (rows.end - rows.start) * grid.cell.1);
+        draw_rows(
+            &grid,
+            rows,
+            &mut strip,
+            splashes,
+            &geometry.params,
+            options,
+            cells,
+        )?;
+        sink(start * grid.cell.1, strip)?;
+    }
+    Ok(())
+}
+
+/// Draw `rows` of the grid into `target`, which starts at the first of them
+fn draw_rows(
+    grid: &Grid,
+    rows: Range<u32>,
+    target: &mut RgbImage,
+    splashes: &[SplashInput],
+    params: &TileParams,
+    options: BuildOptions,
+    cells: Option<&CellCache>,
+) -> anyhow::Result<()> {
+    let target = Mutex::new(target);
+    grid.placements(rows)
+        .into_par_iter()
+        .try_for_each(|(index, corners)| {
+            let cell = splashes[index]
+                .cell(params, options.resize, cells)?
+                .to_rgb8();
+            let mut target = target.lock().unwrap();
+            for (x, y) in corners {
+                target.copy_from(&cell, x, y)?;
+            }
+            Ok(())
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs, io::Cursor, path::PathBuf};
+
+    use image::{DynamicImage, GenericImageView, ImageOutputFormat, Rgb, RgbImage};
+
+    use crate::{build_cache::CellCache, cdragon::fixtures};
+
+    use proptest::prelude::*;
+
+    use super::{
+        build_tile, compose, find_optimal_tile, find_optimal_tile_with, BuildOptions, Encoding,
+        Grid, ScoreWeights, SplashInput,
+    };
+
+    #[test]
+    fn grids_match_splashes() {
+        // 16:9 splashes fill a 16:9 monitor without cropping
+        let params = find_optimal_tile((1280, 720), (1920, 1080), 4, (0, 0)).unwrap();
+        assert_eq!(params.dims, (2, 2));
+        assert_eq!(params.image_adjust, (0, 0));
+        // the old search never stopped without a minimum size
+        assert!(find_optimal_tile((1280, 720), (1920, 1080), 1_000_000, (0, 0)).is_some());
+        assert!(find_optimal_tile((1280, 720), (1920, 1080), 4, (1920, 600)).is_none());
+        assert!(find_optimal_tile((0, 720), (1920, 1080), 4, (0, 0)).is_none());
+
+        // a heavy count weight wastes fewer cells at the cost of cropping
+        let exact = ScoreWeights {
+            count: 100.0,
+            ..ScoreWeights::default()
+        };
+        let params = find_optimal_tile_with((1280, 720), (1920, 1080), 5, (0, 0), &exact).unwrap();
+        assert_eq!(params.dims.0 * params.dims.1, 5);
+    }
+
+    #[test]
+    fn compose_in_memory() {
+        let red = DynamicImage::ImageRgb8(RgbImage::from_pixel(160, 90, Rgb([255, 0, 0])));
+        let mut blue = Vec::new();
+        DynamicImage::ImageRgb8(RgbImage::from_pixel(160, 90, Rgb([0, 0, 255])))
+            .write_to(&mut Cursor::new(&mut blue), ImageOutputFormat::Png)
+            .unwrap();
+        let splashes = [SplashInput::Image(&red), SplashInput::Bytes(&blue)];
+        let tile = compose(&splashes, (640, 360), (0, 0), BuildOptions::default())
+            .unwrap()
+            .unwrap();
+        assert_eq!(tile.image.dimensions(), tile.geometry.canvas);
+        for cell in &tile.geometry.cells {
+            let pixel = tile
+                .image
+                .get_pixel(cell.x + cell.width / 2, cell.y + cell.height / 2);
+            let expected = match cell.splash {
+                0 => Rgb([255, 0, 0]),
+                _ => Rgb([0, 0, 255]),
+            };
+            assert_eq!(pixel, &expected);
+        }
+
+        for encoding in [Encoding::Png, Encoding::Jpeg(90)] {
+            let encoded = tile.encode(encoding).unwrap();
+            let decoded = image::load_from_memory(&encoded).unwrap();
+            assert_eq!(decoded.dimensions(), tile.geometry.canvas);
+        }
+        assert!(compose(&[], (640, 360), (0, 0), BuildOptions::default())
+            .unwrap()
+            .is_none());
+    }
+
+    proptest! {
+        #[test]
+        fn grids_cover_the_monitor(
+            image in (1u32..4000, 1u32..4000),
+            monitor in (1u32..8000, 1u32..5000),
+            count in 0u32..300,
+            min_cell in (0u32..600, 0u32..600),
+        ) {
+            let Some(params) = find_optimal_tile(image, monitor, count, min_cell) else {
+                // only a minimum cell size rules every grid out
+                prop_assert!(min_cell != (0, 0) || monitor.0 * monitor.1 < count);
+                return Ok(());
+            };
+            let (columns, rows) = params.dims;
+            let cell = params.cell();
+            prop_assert!(columns * rows >= count.max(1));
+            prop_assert!(cell.0 >= min_cell.0 && cell.1 >= min_cell.1);
+            prop_assert!(cell.0 > 0 && cell.1 > 0);
+            prop_assert!(columns * cell.0 >= monitor.0 && columns * cell.0 < monitor.0 + columns);
+            prop_assert!(rows * cell.1 >= monitor.1 && rows * cell.1 < monitor.1 + rows);
+            // splashes are only scaled, never stretched past a pixel of rounding
+            let (scaled_x, scaled_y) = (u64::from(params.image_res.0), u64::from(params.image_res.1));
+            let (image_x, image_y) = (u64::from(image.0), u64::from(image.1));
+            prop_assert!((scaled_x * image_y).abs_diff(scaled_y * image_x) <= image_x + image_y);
+        }
+    }
+
+    #[test]
+    fn batches_fit_the_budget() {
+        let params = find_optimal_tile((160, 90), (1920, 1080), 30, (0, 0)).unwrap();
+        let grid = Grid::new(&params, 30);
+        assert_eq!(grid.rows_per_batch(0), 1);
+        assert_eq!(grid.rows_per_batch(u64::MAX), grid.rows);
+        assert_eq!(grid.rows_per_batch(grid.bytes(2) * 2), 2);
+
+        let placed: usize = grid.placements(0..grid.rows).values().map(Vec::len).sum();
+        assert_eq!(placed as u32, grid.columns * grid.rows);
+        assert!(grid
+            .placements(1..2)
+            .values()
+            .flatten()
+            .all(|(_, y)| *y == 0));
+    }
+
+    #[test]
+    fn failed_builds_keep_the_last_wallpaper() {
+        let good = fixtures::splash("splashes/good.jpg", (160, 90), [255, 0, 0]);
+        let bad = good.with_file_name("bad.jpg");
+        fs::write(&bad, b"not a splash").unwrap();
+        let dir = good.parent().unwrap();
+        let cells = CellCache::open().unwrap();
+        let build = |splash_paths: &[PathBuf]| {
+            let options = BuildOptions::default();
+            build_tile(
+                splash_paths,
+                (640, 360),
+                (0, 0),
+                "kept",
+                dir,
+                options,
+                &cells,
+            )
+        };
+        let path = build(&[good.clone(), good.clone()]).unwrap().unwrap().path;
+        let built = fs::read(&path).unwrap();
+        assert!(build(&[good.clone(), bad]).is_err());
+        assert_eq!(fs::read(&path).unwrap(), built);
+        assert_eq!(fs::read_dir(dir).unwrap().count(), 3);
+    }
+
+    #[test]
+    fn large_tiles_are_streamed() {
+        let splash_paths: Vec<_> = (0..3)
+            .map(|index| {
+                fixtures::splash(
+                    &format!("splashes/{index}.jpg"),
+                    (160, 90),
+                    [index * 80, 0, 0],
                 )
             })
             .collect();
-        println!("Done");
-        let mut new_image: RgbaImage = ImageBuffer::new(
-            params.dims.0 * (params.image_res.0 - params.image_adjust.0),
-            params.dims.1 * (params.image_res.1 - params.image_adjust.1),
+        let dir = splash_paths[0].parent().unwrap();
+        let cells = CellCache::open().unwrap();
+        let build = |name, memory_limit| {
+            let options = BuildOptions {
+                memory_limit,
+                ..BuildOptions::default()
+            };
+            build_tile(
+                &splash_paths,
+                (640, 360),
+                (0, 0),
+                name,
+                dir,
+                options,
+                &cells,
+            )
+            .unwrap()
+            .unwrap()
+            .path
+        };
+        let whole = build("whole", BuildOptions::default().memory_limit);
+        let streamed = build("streamed", 1);
+        assert_eq!(whole.extension().unwrap(), "jpg");
+        assert_eq!(streamed.extension().unwrap(), "png");
+        let params = find_optimal_tile((160, 90), (640, 360), 3, (0, 0)).unwrap();
+        let grid = Grid::new(&params, 3);
+        assert_eq!(
+            image::image_dimensions(&streamed).unwrap(),
+            (grid.width(), grid.height())
         );
-        let mut count: usize = 0;
-        for i in 0..params.dims.1 {
-            for j in 0..params.dims.0 {
-                let index = count.rem_euclid(adjusted.len());
-                let x = j * adjusted[index].dimensions().0;
-                let y = i * adjusted[index].dimensions().1;
-                new_image.copy_from(&adjusted[index], x, y).unwrap();
-                count += 1;
-            }
-        }
-
-        let mut tile_path = splash_paths.first().unwrap().clone();
-        tile_path.pop();
-        tile_path.push(format!("{}.jpg", name.into()));
-        let _ = new_image.save_with_format(tile_path.clone(), image::ImageFormat::Jpeg);
-        tile_path
-    } else {
-        PathBuf::default()
-    }
-}
-pub fn merge_two(left_path: PathBuf, right_path: PathBuf) {
-    let left_img = image::open(&left_path).unwrap();
-    let right_img = image::open(right_path).unwrap();
-    let (width, height) = (
-        left_img.dimensions().0 + right_img.dimensions().0,
-        left_img.dimensions().1,
-    );
-    let mut tiled_image: RgbaImage = ImageBuffer::new(width, height);
-    tiled_image.copy_from(&left_img, 0, 0).unwrap();
-    tiled_image
-        .copy_from(&right_img, left_img.dimensions().0, 0)
-        .unwrap();
-    let mut merged_path = left_path.clone();
-    merged_path.pop();
-    merged_path.push("merged.jpg");
-    let _ = tiled_image.save_with_format(merged_path, image::ImageFormat::Jpeg);
+        assert_eq!(
+            image::image_dimensions(&whole).unwrap(),
+            image::image_dimensions(&streamed).unwrap()
+        );
+    }
 }
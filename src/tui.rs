@@ -0,0 +1,507 @@
+use std::collections::HashSet;
+
+use anyhow::anyhow;
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind},
+    layout::{Constraint, Layout},
+    style::{Style, Stylize},
+    text::Line,
+    widgets::{Block, List, ListItem, ListState, Paragraph},
+    DefaultTerminal, Frame,
+};
+use uuid::Uuid;
+
+use crate::{
+    app::{App, TileSource},
+    cache::Cached,
+    cdragon::{CDragon, Champion, Skin},
+    config::Config,
+    output::{DownloadResult, SkinSummary},
+};
+
+const HELP: &str = "/ filter  space toggle  a add all  d download  b build  tab switch  q quit";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pane {
+    Champions,
+    Skins,
+}
+
+/// Something the browser asks the caller to do to the app
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    Quit,
+    /// Add the skin to the tile, or remove it if it's already there
+    Toggle(u64),
+    Add(Vec<u64>),
+    Download(u64),
+    Build,
+}
+
+/// The state of the champion and skin lists, kept apart from the terminal so it can be tested
+pub struct Browser<'a> {
+    champions: Vec<&'a Champion>,
+    /// The patch the skins are from, splashes are downloaded per patch
+    patch: &'a str,
+    pub focus: Pane,
+    pub filter: String,
+    /// Whether keys are typed into the filter
+    pub filtering: bool,
+    pub champion_state: ListState,
+    pub skin_state: ListState,
+    pub status: String,
+}
+
+impl<'a> Browser<'a> {
+    pub fn new(cdragon: &'a CDragon) -> Self {
+        let mut champions: Vec<&Champion> = cdragon.champions.values().collect();
+        champions.sort_by(|a, b| a.name.cmp(&b.name));
+        Self {
+            champions,
+            patch: &cdragon.patch,
+            focus: Pane::Champions,
+            filter: String::new(),
+            filtering: false,
+            champion_state: ListState::default().with_selected(Some(0)),
+            skin_state: ListState::default().with_selected(Some(0)),
+            status: String::new(),
+        }
+    }
+
+    fn champion_matches(&self, champion: &Champion) -> bool {
+        let filter = self.filter.to_lowercase();
+        champion.name.to_lowercase().contains(&filter)
+            || champion.alias.to_lowercase().contains(&filter)
+    }
+
+    /// Champions whose name or any of whose skins match the filter
+    pub fn champions(&self) -> Vec<&'a Champion> {
+        self.champions
+            .iter()
+            .filter(|champion| {
+                self.champion_matches(champion) || !self.filtered_skins(champion).is_empty()
+            })
+            .copied()
+            .collect()
+    }
+
+    fn filtered_skins(&self, champion: &'a Champion) -> Vec<&'a Skin> {
+        let filter = self.filter.to_lowercase();
+        let mut skins: Vec<&Skin> = champion
+            .skins
+            .values()
+            .filter(|skin| skin.name.to_lowercase().contains(&filter))
+            .collect();
+        skins.sort_by_key(|skin| skin.id);
+        skins
+    }
+
+    pub fn champion(&self) -> Option<&'a Champion> {
+        self.champions()
+            .get(self.champion_state.selected()?)
+            .copied()
+    }
+
+    /// The highlighted champion's skins, all of them when the filter matches the champion
+    pub fn skins(&self) -> Vec<&'a Skin> {
+        let Some(champion) = self.champion() else {
+            return Vec::new();
+        };
+        match self.champion_matches(champion) {
+            true => {
+                let mut skins: Vec<&Skin> = champion.skins.values().collect();
+                skins.sort_by_key(|skin| skin.id);
+                skins
+            }
+            false => self.filtered_skins(champion),
+        }
+    }
+
+    pub fn skin(&self) -> Option<&'a Skin> {
+        self.skins().get(self.skin_state.selected()?).copied()
+    }
+
+    fn reset(&mut self) {
+        self.champion_state.select(Some(0));
+        self.skin_state.select(Some(0));
+    }
+
+    fn step(&mut self, forward: bool) {
+        let len = match self.focus {
+            Pane::Champions => self.champions().len(),
+            Pane::Skins => self.skins().len(),
+        };
+        let state = match self.focus {
+            Pane::Champions => &mut self.champion_state,
+            Pane::Skins => &mut self.skin_state,
+        };
+        if len == 0 {
+            return;
+        }
+        let selected = state.selected().unwrap_or(0).min(len - 1);
+        state.select(Some(match forward {
+            true => (selected + 1) % len,
+            false => (selected + len - 1) % len,
+        }));
+        if self.focus == Pane::Champions {
+            self.skin_state.select(Some(0));
+        }
+    }
+
+    /// Update the browser for a key press, returning what the app should do
+    pub fn handle(&mut self, key: KeyEvent) -> Option<Action> {
+        if self.filtering {
+            match key.code {
+                KeyCode::Char(c) => self.filter.push(c),
+                KeyCode::Backspace => {
+                    self.filter.pop();
+                }
+                KeyCode::Esc => {
+                    self.filter.clear();
+                    self.filtering = false;
+                }
+                KeyCode::Enter => self.filtering = false,
+                _ => return None,
+            }
+            self.reset();
+            return None;
+        }
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return Some(Action::Quit),
+            KeyCode::Char('/') => self.filtering = true,
+            KeyCode::Down | KeyCode::Char('j') => self.step(true),
+            KeyCode::Up | KeyCode::Char('k') => self.step(false),
+            KeyCode::Left | KeyCode::Char('h') => self.focus = Pane::Champions,
+            KeyCode::Right | KeyCode::Char('l') | KeyCode::Enter => self.focus = Pane::Skins,
+            KeyCode::Tab => {
+                self.focus = match self.focus {
+                    Pane::Champions => Pane::Skins,
+                    Pane::Skins => Pane::Champions,
+                }
+            }
+            KeyCode::Char(' ') => match self.focus {
+                Pane::Champions => self.focus = Pane::Skins,
+                Pane::Skins => return self.skin().map(|skin| Action::Toggle(skin.id)),
+            },
+            KeyCode::Char('a') => {
+                return Some(Action::Add(
+                    self.skins().iter().map(|skin| skin.id).collect(),
+                ))
+            }
+            KeyCode::Char('d') => return self.skin().map(|skin| Action::Download(skin.id)),
+            KeyCode::Char('b') => return Some(Action::Build),
+            _ => {}
+        }
+        None
+    }
+
+    fn draw(&mut self, frame: &mut Frame, app: &App, tile_id: Uuid) {
+        let tile = app.tiles.iter().find(|tile| tile.id == tile_id);
+        let in_tile: HashSet<u64> = tile
+            .map(|tile| tile.splash_ids.iter().copied().collect())
+            .unwrap_or_default();
+        let [main, detail, filter, footer] = Layout::vertical([
+            Constraint::Min(5),
+            Constraint::Length(6),
+            Constraint::Length(1),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        let [left, right] =
+            Layout::horizontal([Constraint::Percentage(30), Constraint::Percentage(70)])
+                .areas(main);
+        let focused = |pane: Pane| match self.focus == pane {
+            true => Style::new().bold(),
+            false => Style::new().dim(),
+        };
+
+        let champions: Vec<ListItem> = self
+            .champions()
+            .iter()
+            .map(|champion| {
+                let count = champion
+                    .skins
+                    .keys()
+                    .filter(|id| in_tile.contains(id))
+                    .count();
+                match count {
+                    0 => ListItem::new(champion.name.clone()),
+                    count => ListItem::new(format!("{} ({count})", champion.name)),
+                }
+            })
+            .collect();
+        frame.render_stateful_widget(
+            List::new(champions)
+                .block(
+                    Block::bordered()
+                        .title("Champions")
+                        .border_style(focused(Pane::Champions)),
+                )
+                .highlight_style(Style::new().reversed()),
+            left,
+            &mut self.champion_state,
+        );
+
+        let skins: Vec<ListItem> = self
+            .skins()
+            .iter()
+            .map(|skin| {
+                let member = match in_tile.contains(&skin.id) {
+                    true => "[x]",
+                    false => "[ ]",
+                };
+                let downloaded = match app.is_downloaded(skin.id, self.patch) {
+                    true => "\u{2713}",
+                    false => " ",
+                };
+                ListItem::new(format!("{member} {downloaded} {}", skin.name))
+            })
+            .collect();
+        let title = match tile {
+            Some(tile) if matches!(tile.source, TileSource::Query(_)) => {
+                format!("Skins, {} is a smart tile and can't be edited", tile.name)
+            }
+            Some(tile) => format!("Skins in {}", tile.name),
+            None => String::from("Skins"),
+        };
+        frame.render_stateful_widget(
+            List::new(skins)
+                .block(
+                    Block::bordered()
+                        .title(title)
+                        .border_style(focused(Pane::Skins)),
+                )
+                .highlight_style(Style::new().reversed()),
+            right,
+            &mut self.skin_state,
+        );
+
+        let details = match self.skin() {
+            Some(skin) => {
+                let summary = SkinSummary::from(skin);
+                vec![
+                    Line::from(format!("{} ({})", summary.name, summary.id)),
+                    Line::from(format!(
+                        "rarity: {}  legacy: {}",
+                        summary.rarity.trim_start_matches('k'),
+                        summary.is_legacy
+                    )),
+                    Line::from(format!("skin lines: {}", summary.skin_lines.join(", "))),
+                    Line::from(format!(
+                        "downloaded: {}  in tile: {}",
+                        app.is_downloaded(skin.id, self.patch),
+                        in_tile.contains(&skin.id)
+                    )),
+                ]
+            }
+            None => vec![Line::from("no skin selected")],
+        };
+        frame.render_widget(Paragraph::new(details).block(Block::bordered()), detail);
+
+        let filter_line = match self.filtering {
+            true => format!("/{}\u{2588}", self.filter),
+            false if self.filter.is_empty() => String::new(),
+            false => format!("filter: {}", self.filter),
+        };
+        frame.render_widget(Paragraph::new(filter_line), filter);
+        let footer_line = match self.status.is_empty() {
+            true => HELP.to_string(),
+            false => self.status.clone(),
+        };
+        frame.render_widget(Paragraph::new(footer_line).dim(), footer);
+    }
+}
+
+/// Browse champions and skins full screen, curating the tile `tile_id`
+pub fn run(app: &mut App, cdragon: &CDragon, config: &Config, tile_id: Uuid) -> anyhow::Result<()> {
+    let mut terminal = ratatui::init();
+    let result = event_loop(&mut terminal, app, cdragon, config, tile_id);
+    ratatui::restore();
+    result
+}
+
+fn event_loop(
+    terminal: &mut DefaultTerminal,
+    app: &mut App,
+    cdragon: &CDragon,
+    config: &Config,
+    tile_id: Uuid,
+) -> anyhow::Result<()> {
+    let mut browser = Browser::new(cdragon);
+    loop {
+        terminal.draw(|frame| browser.draw(frame, app, tile_id))?;
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        let Some(action) = browser.handle(key) else {
+            continue;
+        };
+        if action == Action::Quit {
+            return Ok(());
+        }
+        // downloads and builds take a while, so say what's happening first
+        browser.status = match &action {
+            Action::Download(_) => String::from("Downloading..."),
+            Action::Build => String::from("Building..."),
+            _ => String::new(),
+        };
+        terminal.draw(|frame| browser.draw(frame, app, tile_id))?;
+        browser.status = match apply(app, cdragon, config, tile_id, action) {
+            Ok(status) => status,
+            Err(err) => format!("{err:#}"),
+        };
+    }
+}
+
+fn apply(
+    app: &mut App,
+    cdragon: &CDragon,
+    config: &Config,
+    tile_id: Uuid,
+    action: Action,
+) -> anyhow::Result<String> {
+    match action {
+        Action::Quit => Ok(String::new()),
+        Action::Toggle(id) => {
+            let tile = app.tile_find(Some(&tile_id.to_string()))?;
+            match tile.splash_ids.contains(&id) {
+                true => {
+                    app.tile_remove_splashes(tile_id, &HashSet::from([id]))?;
+                    Ok(format!("Removed {id}"))
+                }
+                false => {
+                    app.tile_add_splash(&tile_id, &id)?;
+                    Ok(format!("Added {id}"))
+                }
+            }
+        }
+        Action::Add(ids) => {
+            app.transaction(|app| {
+                ids.iter()
+                    .try_for_each(|id| app.tile_add_splash(&tile_id, id))
+            })?;
+            Ok(format!("Added {} skins", ids.len()))
+        }
+        Action::Download(id) => {
+            if app.is_downloaded(id, &cdragon.patch) {
+                return Ok(format!("{id} is already downloaded"));
+            }
+            let downloads = app.download_missing(cdragon, &[id], config)?;
+            let result = DownloadResult::all(&downloads, cdragon)
+                .pop()
+                .ok_or(anyhow!("skin {id} not found"))?;
+            match result.error {
+                Some(error) => Err(anyhow!(error)),
+                None => Ok(format!("Downloaded {}", result.skin.name)),
+            }
+        }
+        Action::Build => {
+            let output = app.tile_build(tile_id, cdragon, config)?;
+            let built = output
+                .files
+                .iter()
+                .map(|file| file.path.display().to_string())
+                .collect::<Vec<String>>()
+                .join(", ");
+            Ok(match output.failed.len() {
+                0 => format!("Built {built}"),
+                failed => format!("Built {built}, {failed} splashes couldn't be downloaded"),
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use ratatui::{
+        backend::TestBackend,
+        crossterm::event::{KeyCode, KeyEvent, KeyModifiers},
+        Terminal,
+    };
+
+    use crate::{app::App, cdragon::fixtures};
+
+    use super::{Action, Browser, Pane};
+
+    fn press(browser: &mut Browser, code: KeyCode) -> Option<Action> {
+        browser.handle(KeyEvent::new(code, KeyModifiers::NONE))
+    }
+
+    #[test]
+    fn filter_as_you_type() {
+        let cdragon = fixtures::cdragon();
+        let mut browser = Browser::new(&cdragon);
+        let names = |browser: &Browser| -> Vec<String> {
+            browser
+                .champions()
+                .iter()
+                .map(|champion| champion.name.clone())
+                .collect()
+        };
+        assert_eq!(names(&browser), ["Ahri", "Annie", "Jinx"]);
+
+        press(&mut browser, KeyCode::Char('/'));
+        for c in "star".chars() {
+            press(&mut browser, KeyCode::Char(c));
+        }
+        assert_eq!(names(&browser), ["Ahri", "Jinx"]);
+        assert_eq!(browser.skins().len(), 1);
+        // q is typed into the filter rather than quitting
+        assert_eq!(press(&mut browser, KeyCode::Char('q')), None);
+        assert!(browser.champions().is_empty());
+        press(&mut browser, KeyCode::Esc);
+        assert!(!browser.filtering);
+        assert_eq!(names(&browser).len(), 3);
+    }
+
+    #[test]
+    fn navigate_and_toggle() {
+        let cdragon = fixtures::cdragon();
+        let mut browser = Browser::new(&cdragon);
+        press(&mut browser, KeyCode::Down);
+        assert_eq!(browser.champion().unwrap().name, "Annie");
+        assert_eq!(press(&mut browser, KeyCode::Char(' ')), None);
+        assert_eq!(browser.focus, Pane::Skins);
+        press(&mut browser, KeyCode::Down);
+        assert_eq!(
+            press(&mut browser, KeyCode::Char(' ')),
+            Some(Action::Toggle(1001))
+        );
+        assert_eq!(
+            press(&mut browser, KeyCode::Char('a')),
+            Some(Action::Add(vec![1000, 1001]))
+        );
+        press(&mut browser, KeyCode::Up);
+        press(&mut browser, KeyCode::Up);
+        assert_eq!(browser.skin().unwrap().id, 1001);
+        assert_eq!(press(&mut browser, KeyCode::Char('q')), Some(Action::Quit));
+    }
+
+    #[test]
+    fn draw_tile_membership() {
+        let cdragon = fixtures::cdragon();
+        let mut app = App::default();
+        let id = app.tile_new("mine").unwrap();
+        app.tile_add_splash(&id, &1001).unwrap();
+        let mut browser = Browser::new(&cdragon);
+        press(&mut browser, KeyCode::Down);
+
+        let mut terminal = Terminal::new(TestBackend::new(80, 20)).unwrap();
+        terminal
+            .draw(|frame| browser.draw(frame, &app, id))
+            .unwrap();
+        let screen: String = terminal
+            .backend()
+            .buffer()
+            .content()
+            .iter()
+            .map(|cell| cell.symbol())
+            .collect();
+        assert!(screen.contains("Annie (1)"));
+        assert!(screen.contains("[x]   Goth Annie"));
+        assert!(screen.contains("Skins in mine"));
+    }
+}
@@ -0,0 +1,425 @@
+use std::{
+    env, fmt,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use anyhow::{anyhow, Context};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+/// A monitor and the wallpaper that should be shown on it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Output {
+    /// The name the display server uses for the monitor, e.g. `DP-1`
+    pub name: String,
+    pub wallpaper: PathBuf,
+}
+
+/// The parts of the environment used to pick a backend
+#[derive(Debug, Clone, Default)]
+pub struct Environment {
+    /// `XDG_CURRENT_DESKTOP`, lowercased
+    pub desktop: String,
+    pub wayland: bool,
+    pub sway: bool,
+    pub x11: bool,
+}
+
+impl Environment {
+    pub fn from_env() -> Self {
+        Self {
+            desktop: env::var("XDG_CURRENT_DESKTOP")
+                .unwrap_or_default()
+                .to_lowercase(),
+            wayland: env::var_os("WAYLAND_DISPLAY").is_some(),
+            sway: env::var_os("SWAYSOCK").is_some(),
+            x11: env::var_os("DISPLAY").is_some(),
+        }
+    }
+}
+
+/// A command that sets the wallpaper
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WallpaperCommand {
+    pub program: String,
+    pub args: Vec<String>,
+    /// Whether the program keeps running to draw the wallpaper, like `swaybg`
+    pub detach: bool,
+    /// Whether it's fine for the program to fail, like `pkill` when there's nothing to stop
+    pub optional: bool,
+}
+
+impl WallpaperCommand {
+    fn new(program: &str, args: &[&str]) -> Self {
+        Self {
+            program: program.to_string(),
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+            detach: false,
+            optional: false,
+        }
+    }
+
+    pub fn run(&self) -> anyhow::Result<()> {
+        let mut command = Command::new(&self.program);
+        command.args(&self.args);
+        if self.detach {
+            command
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .spawn()
+                .with_context(|| format!("failed to start {}", self.program))?;
+            return Ok(());
+        }
+        let status = command
+            .status()
+            .with_context(|| format!("failed to run {}", self.program))?;
+        match status.success() || self.optional {
+            true => Ok(()),
+            false => Err(anyhow!("`{self}` exited with {status}")),
+        }
+    }
+}
+
+impl fmt::Display for WallpaperCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", shell_quote(&self.program))?;
+        for arg in &self.args {
+            write!(f, " {}", shell_quote(arg))?;
+        }
+        if self.detach {
+            write!(f, " &")?;
+        }
+        if self.optional {
+            write!(f, " || true")?;
+        }
+        Ok(())
+    }
+}
+
+fn shell_quote(arg: &str) -> String {
+    let safe = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./=:@%+,".contains(c));
+    match safe {
+        true => arg.to_string(),
+        false => format!("'{}'", arg.replace('\'', r"'\''")),
+    }
+}
+
+fn path_str(path: &Path) -> anyhow::Result<&str> {
+    path.to_str()
+        .ok_or(anyhow!("wallpaper path {:?} isn't valid utf-8", path))
+}
+
+/// The `file://` URI of `path`, percent-encoded
+fn file_uri(path: &Path) -> anyhow::Result<String> {
+    Url::from_file_path(path)
+        .map(String::from)
+        .map_err(|_| anyhow!("wallpaper path {:?} isn't absolute", path))
+}
+
+/// A program that can set the desktop wallpaper
+pub trait WallpaperBackend {
+    fn kind(&self) -> BackendKind;
+
+    /// The commands that show each output's wallpaper on its monitor
+    fn commands(&self, outputs: &[Output]) -> anyhow::Result<Vec<WallpaperCommand>>;
+
+    /// Set the wallpapers, or only print the commands when `dry_run` is set
+    fn apply(&self, outputs: &[Output], dry_run: bool) -> anyhow::Result<Vec<WallpaperCommand>> {
+        if outputs.is_empty() {
+            return Err(anyhow!("there aren't any wallpapers to set"));
+        }
+        let commands = self.commands(outputs)?;
+        if !dry_run {
+            for command in &commands {
+                command.run()?;
+            }
+        }
+        Ok(commands)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum BackendKind {
+    Feh,
+    Swaybg,
+    Gnome,
+    Xfce,
+    Kde,
+}
+
+impl BackendKind {
+    /// Pick the backend that fits the running desktop
+    pub fn detect(env: &Environment) -> Option<BackendKind> {
+        let desktop = &env.desktop;
+        if desktop.contains("gnome") || desktop.contains("unity") {
+            Some(BackendKind::Gnome)
+        } else if desktop.contains("kde") {
+            Some(BackendKind::Kde)
+        } else if desktop.contains("xfce") {
+            Some(BackendKind::Xfce)
+        } else if env.sway || env.wayland {
+            Some(BackendKind::Swaybg)
+        } else if env.x11 {
+            Some(BackendKind::Feh)
+        } else {
+            None
+        }
+    }
+
+    pub fn backend(self) -> Box<dyn WallpaperBackend> {
+        match self {
+            BackendKind::Feh => Box::new(Feh),
+            BackendKind::Swaybg => Box::new(Swaybg),
+            BackendKind::Gnome => Box::new(Gnome),
+            BackendKind::Xfce => Box::new(Xfce),
+            BackendKind::Kde => Box::new(Kde),
+        }
+    }
+}
+
+/// X11 setter, feh hands out the images to monitors in order
+pub struct Feh;
+
+impl WallpaperBackend for Feh {
+    fn kind(&self) -> BackendKind {
+        BackendKind::Feh
+    }
+
+    fn commands(&self, outputs: &[Output]) -> anyhow::Result<Vec<WallpaperCommand>> {
+        let mut command = WallpaperCommand::new("feh", &["--no-fehbg", "--bg-fill"]);
+        for output in outputs {
+            command.args.push(path_str(&output.wallpaper)?.to_string());
+        }
+        Ok(vec![command])
+    }
+}
+
+/// Wayland setter for sway and other wlroots compositors
+///
+/// `swaybg` keeps running to draw the wallpaper, so the ones started before are stopped first.
+pub struct Swaybg;
+
+impl WallpaperBackend for Swaybg {
+    fn kind(&self) -> BackendKind {
+        BackendKind::Swaybg
+    }
+
+    fn commands(&self, outputs: &[Output]) -> anyhow::Result<Vec<WallpaperCommand>> {
+        let mut stop = WallpaperCommand::new("pkill", &["-x", "swaybg"]);
+        stop.optional = true;
+        let mut command = WallpaperCommand::new("swaybg", &[]);
+        command.detach = true;
+        for output in outputs {
+            command.args.extend(
+                [
+                    "-o",
+                    &output.name,
+                    "-i",
+                    path_str(&output.wallpaper)?,
+                    "-m",
+                    "fill",
+                ]
+                .map(String::from),
+            );
+        }
+        Ok(vec![stop, command])
+    }
+}
+
+/// GNOME only has one background for every monitor, so the first output's wallpaper is used
+pub struct Gnome;
+
+impl WallpaperBackend for Gnome {
+    fn kind(&self) -> BackendKind {
+        BackendKind::Gnome
+    }
+
+    fn commands(&self, outputs: &[Output]) -> anyhow::Result<Vec<WallpaperCommand>> {
+        let output = outputs
+            .first()
+            .ok_or(anyhow!("there aren't any wallpapers to set"))?;
+        let uri = file_uri(&output.wallpaper)?;
+        let schema = "org.gnome.desktop.background";
+        Ok(vec![
+            WallpaperCommand::new("gsettings", &["set", schema, "picture-uri", &uri]),
+            WallpaperCommand::new("gsettings", &["set", schema, "picture-uri-dark", &uri]),
+            WallpaperCommand::new("gsettings", &["set", schema, "picture-options", "zoom"]),
+        ])
+    }
+}
+
+pub struct Xfce;
+
+impl WallpaperBackend for Xfce {
+    fn kind(&self) -> BackendKind {
+        BackendKind::Xfce
+    }
+
+    fn commands(&self, outputs: &[Output]) -> anyhow::Result<Vec<WallpaperCommand>> {
+        outputs
+            .iter()
+            .map(|output| {
+                let property = format!(
+                    "/backdrop/screen0/monitor{}/workspace0/last-image",
+                    output.name
+                );
+                Ok(WallpaperCommand::new(
+                    "xfconf-query",
+                    &[
+                        "--channel",
+                        "xfce4-desktop",
+                        "--property",
+                        &property,
+                        "--create",
+                        "--type",
+                        "string",
+                        "--set",
+                        path_str(&output.wallpaper)?,
+                    ],
+                ))
+            })
+            .collect()
+    }
+}
+
+/// KDE Plasma, through a desktop script evaluated by plasmashell over dbus
+pub struct Kde;
+
+impl WallpaperBackend for Kde {
+    fn kind(&self) -> BackendKind {
+        BackendKind::Kde
+    }
+
+    fn commands(&self, outputs: &[Output]) -> anyhow::Result<Vec<WallpaperCommand>> {
+        let uris = outputs
+            .iter()
+            .map(|output| Ok(serde_json::to_string(&file_uri(&output.wallpaper)?)?))
+            .collect::<anyhow::Result<Vec<String>>>()?
+            .join(",");
+        let script = format!(
+            "var uris = [{uris}];\
+             desktops().forEach(function (d) {{\
+             if (d.screen < 0 || d.screen >= uris.length) return;\
+             d.wallpaperPlugin = 'org.kde.image';\
+             d.currentConfigGroup = ['Wallpaper', 'org.kde.image', 'General'];\
+             d.writeConfig('Image', uris[d.screen]);\
+             }});"
+        );
+        Ok(vec![WallpaperCommand::new(
+            "qdbus",
+            &[
+                "org.kde.plasmashell",
+                "/PlasmaShell",
+                "org.kde.PlasmaShell.evaluateScript",
+                &script,
+            ],
+        )])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use super::{BackendKind, Environment, Output};
+
+    fn outputs() -> Vec<Output> {
+        vec![
+            Output {
+                name: String::from("DP-1"),
+                wallpaper: PathBuf::from("/tiles/star guardian-0.jpg"),
+            },
+            Output {
+                name: String::from("HDMI-A-1"),
+                wallpaper: PathBuf::from("/tiles/star guardian-1.jpg"),
+            },
+        ]
+    }
+
+    #[test]
+    fn detect_backend() {
+        let env = |desktop: &str, wayland, sway, x11| Environment {
+            desktop: desktop.to_string(),
+            wayland,
+            sway,
+            x11,
+        };
+        assert_eq!(
+            BackendKind::detect(&env("ubuntu:gnome", true, false, true)),
+            Some(BackendKind::Gnome)
+        );
+        assert_eq!(
+            BackendKind::detect(&env("kde", true, false, true)),
+            Some(BackendKind::Kde)
+        );
+        assert_eq!(
+            BackendKind::detect(&env("xfce", false, false, true)),
+            Some(BackendKind::Xfce)
+        );
+        assert_eq!(
+            BackendKind::detect(&env("sway", true, true, false)),
+            Some(BackendKind::Swaybg)
+        );
+        assert_eq!(
+            BackendKind::detect(&env("", false, false, true)),
+            Some(BackendKind::Feh)
+        );
+        assert_eq!(BackendKind::detect(&env("", false, false, false)), None);
+    }
+
+    #[test]
+    fn dry_run_commands() {
+        let feh = BackendKind::Feh
+            .backend()
+            .apply(&outputs(), true)
+            .unwrap()
+            .iter()
+            .map(|command| command.to_string())
+            .collect::<Vec<String>>();
+        assert_eq!(
+            feh,
+            vec!["feh --no-fehbg --bg-fill '/tiles/star guardian-0.jpg' '/tiles/star guardian-1.jpg'"]
+        );
+
+        let swaybg = BackendKind::Swaybg
+            .backend()
+            .apply(&outputs(), true)
+            .unwrap();
+        assert_eq!(swaybg.len(), 2);
+        assert_eq!(swaybg[0].to_string(), "pkill -x swaybg || true");
+        assert!(swaybg[1].detach);
+        assert_eq!(
+            &swaybg[1].args[..4],
+            &["-o", "DP-1", "-i", "/tiles/star guardian-0.jpg"]
+        );
+
+        let xfce = BackendKind::Xfce.backend().apply(&outputs(), true).unwrap();
+        assert_eq!(xfce.len(), 2);
+        assert!(xfce[1].args.contains(&String::from(
+            "/backdrop/screen0/monitorHDMI-A-1/workspace0/last-image"
+        )));
+
+        let gnome = BackendKind::Gnome
+            .backend()
+            .apply(&outputs(), true)
+            .unwrap();
+        assert_eq!(gnome[0].args[3], "file:///tiles/star%20guardian-0.jpg");
+
+        let kde = BackendKind::Kde.backend().apply(&outputs(), true).unwrap();
+        assert!(kde[0].args[3].contains(
+            r#"["file:///tiles/star%20guardian-0.jpg","file:///tiles/star%20guardian-1.jpg"]"#
+        ));
+    }
+
+    #[test]
+    fn nothing_to_set() {
+        assert!(BackendKind::Feh.backend().apply(&[], true).is_err());
+    }
+}